@@ -1,15 +1,46 @@
 use bytes::Bytes;
-use tokio_stream::Stream;
+use tokio::fs::File;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::ReaderStream;
 
 use crate::response::ResponseError;
 
 pub enum Body {
     Bytes(Bytes),
     Stream(Box<dyn Stream<Item = Result<Bytes, ResponseError>> + Send + Sync + Unpin>),
+    File(FileBody),
 }
 
 impl From<Bytes> for Body {
     fn from(bytes: Bytes) -> Self {
         Body::Bytes(bytes)
     }
-}
\ No newline at end of file
+}
+
+/// File content, sent whole or as the byte ranges requested by the client
+pub struct FileBody {
+    pub(crate) file: File,
+    pub(crate) len: u64,
+    /// `None` to send the whole file
+    pub(crate) segments: Option<Vec<FileSegment>>,
+    /// written after the last segment, the closing boundary of a `multipart/byteranges` body
+    pub(crate) tail: Bytes,
+}
+
+/// `len` bytes of the file from `start`, preceded by `head`
+pub(crate) struct FileSegment {
+    pub head: Bytes,
+    pub start: u64,
+    pub len: u64,
+}
+
+impl FileBody {
+    pub(crate) fn new(file: File, len: u64) -> Self {
+        FileBody { file, len, segments: None, tail: Bytes::new() }
+    }
+
+    /// The whole file as a stream of chunks
+    pub(crate) fn into_stream(self) -> Box<dyn Stream<Item = Result<Bytes, ResponseError>> + Send + Sync + Unpin> {
+        Box::new(ReaderStream::new(self.file).map(|res| res.map_err(Into::into)))
+    }
+}
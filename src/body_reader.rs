@@ -1,67 +1,686 @@
-use tokio::{io::AsyncReadExt, sync::Mutex};
-use std::io;
+use bytes::{Buf, Bytes};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+use tokio_stream::Stream;
+use tokio_util::io::StreamReader;
+use std::{future::poll_fn, io, pin::Pin, sync::{atomic::{AtomicUsize, Ordering}, Mutex, MutexGuard, PoisonError}, task::{ready, Context, Poll}};
+use crate::{content_encoding::ContentDecoder, headers::{is_token, HeaderName, HeaderValue, Headers}, parser::parse_field_line, query::Query};
+#[cfg(feature = "json")]
+use crate::RequestError;
 use super::tcp_io::TcpIO;
 
-pub struct BodyReader(Mutex<InnerBodyReader>);
+/// Longest chunk-size or trailer line accepted in a chunked body
+const MAX_CHUNK_LINE_LEN: usize = 4096;
+/// Maximum number of trailer fields accepted after the last chunk
+const MAX_TRAILERS: usize = 64;
+/// Encoded bytes fed to the content decoder at once
+const DECODE_INPUT_LEN: usize = 1024;
+
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Error wrapped in the `io::Error` returned by reads once the body goes over its size limit
+#[derive(thiserror::Error, Debug)]
+#[error("request body exceeds the limit of {0} bytes")]
+pub struct PayloadTooLarge(pub usize);
+
+/// Request body, decoded from the connection as it is read.
+///
+/// Besides the async methods, it can be used as a `tokio::io::AsyncRead` (also through `&BodyReader`,
+/// which is what handlers receive) and as a `Stream` of `io::Result<Bytes>`:
+/// ```rust,no_run
+/// # async fn upload(payload: &http_tokio::BodyReader) -> std::io::Result<()> {
+/// let mut file = tokio::fs::File::create("upload.bin").await?;
+/// tokio::io::copy(&mut &*payload, &mut file).await?;
+/// # Ok(())
+/// # }
+/// ```
+/// Stream items share the bytes of decompressed bodies and HTTP/2 frames, bytes read from an HTTP/1 connection
+/// are copied once out of its read buffer.
+///
+/// `AsyncBufRead` is only implemented on an owned `BodyReader`: `poll_fill_buf` lends out bytes past the call,
+/// which `&BodyReader` can't do because its state sits behind a lock that any other `&BodyReader` may take meanwhile.
+/// Handlers get buffered reads from `buffered`, which reads the `Stream` chunks in place instead of copying them again.
+pub struct BodyReader {
+    inner: Mutex<InnerBodyReader>,
+    max_size: AtomicUsize,
+    #[cfg(feature = "json")]
+    content_type: Option<HeaderValue>,
+    #[cfg(feature = "json")]
+    max_json_size: usize,
+}
 
 struct InnerBodyReader {
-    io: TcpIO,
-    remaining: usize,
+    /// `None` for the body of an HTTP/2 stream
+    io: Option<TcpIO>,
+    decoder: Decoder,
+    trailers: Option<Headers>,
+    /// partial chunk-size or trailer line
+    line: Vec<u8>,
+    /// `Expect: 100-continue` received and the interim response not sent yet
+    expect_continue: bool,
+    /// bytes of `100 Continue` already written
+    continue_written: usize,
+    /// body bytes read so far
+    received: usize,
+    /// set once a read went over the size limit
+    exceeded_limit: Option<usize>,
+    /// decodes the `Content-Encoding` of the body, when decompression is enabled
+    content_decoder: Option<ContentDecoder>,
+    /// bound on the decoded size, whatever the body size limit
+    max_decoded_size: usize,
+    /// decoded bytes not consumed yet
+    decoded: Bytes,
+    /// the decoder has been fed the whole body
+    content_done: bool,
+}
+
+enum Decoder {
+    Length { remaining: usize },
+    Chunked(ChunkedState),
+    /// DATA frames of an HTTP/2 stream, `data` is the rest of the last one
+    #[cfg(feature = "http2")]
+    Http2 { stream: h2::RecvStream, data: Bytes, done: bool },
+}
+
+enum ChunkedState {
+    Size,
+    Data { remaining: usize },
+    DataEnd,
+    Trailers { count: usize, trailers: Headers },
+    Done,
+    /// the framing was invalid, nothing more is read
+    Invalid,
 }
 
 impl BodyReader {
+    /// Body delimited by a `Content-Length` of `c_len` bytes
     pub fn new(c_len: usize, io: TcpIO) -> Self {
-        Self(Mutex::new(InnerBodyReader { io, remaining: c_len }))
+        Self::with_decoder(io, Decoder::Length { remaining: c_len })
     }
 
-    pub fn into_io(self) -> TcpIO {
-        self.0.into_inner().io
+    /// Body sent with `Transfer-Encoding: chunked`
+    pub fn chunked(io: TcpIO) -> Self {
+        Self::with_decoder(io, Decoder::Chunked(ChunkedState::Size))
     }
 
-    pub async fn next(&self) -> io::Result<Option<Vec<u8>>> {
-        let mut inner = self.0.lock().await;
+    /// Body of an HTTP/2 request stream, flow control capacity is released as it is read
+    #[cfg(feature = "http2")]
+    pub(crate) fn http2(stream: h2::RecvStream) -> Self {
+        Self::from_inner(None, Decoder::Http2 { stream, data: Bytes::new(), done: false })
+    }
+
+    fn with_decoder(io: TcpIO, decoder: Decoder) -> Self {
+        Self::from_inner(Some(io), decoder)
+    }
 
-        if inner.remaining == 0 {
-            return Ok(None);
+    fn from_inner(io: Option<TcpIO>, decoder: Decoder) -> Self {
+        let inner = InnerBodyReader {
+            io,
+            decoder,
+            trailers: None,
+            line: Vec::new(),
+            expect_continue: false,
+            continue_written: 0,
+            received: 0,
+            exceeded_limit: None,
+            content_decoder: None,
+            max_decoded_size: usize::MAX,
+            decoded: Bytes::new(),
+            content_done: false,
+        };
+        Self {
+            inner: Mutex::new(inner),
+            max_size: AtomicUsize::new(usize::MAX),
+            #[cfg(feature = "json")]
+            content_type: None,
+            #[cfg(feature = "json")]
+            max_json_size: usize::MAX,
         }
+    }
 
-        let to_read = 1024.min(inner.remaining);
-        let mut buf = vec![0u8; to_read];
-        let read = inner.io.reader().read(&mut buf).await?;
+    /// Sets the maximum body size in bytes for this request, overriding the server's `Limits::max_body_size`.
+    ///
+    /// A declared `Content-Length` over the limit fails on the first read, before reading anything,
+    /// a chunked body fails as soon as it goes over the limit; reads then return a `PayloadTooLarge`
+    /// error and the connection answers `413 Payload Too Large` regardless of the handler's response.
+    ///
+    /// A `Content-Length` over the server's limit is answered with `413` before the handler is called,
+    /// so raising the limit here only lets larger chunked bodies through.
+    pub fn set_max_size(&self, max: usize) {
+        self.max_size.store(max, Ordering::Relaxed);
+    }
 
-        if read == 0 {
-            inner.remaining = 0;
-            return Ok(None);
+    /// The limit that was exceeded by a read, or that the declared `Content-Length` goes over if the body wasn't read
+    pub(crate) fn exceeded_limit(&mut self) -> Option<usize> {
+        let limit = *self.max_size.get_mut();
+        let inner = self.inner_mut();
+        match inner.decoder {
+            Decoder::Length { remaining } if inner.exceeded_limit.is_none() && inner.received.saturating_add(remaining) > limit => Some(limit),
+            _ => inner.exceeded_limit,
         }
+    }
+
+    /// Reads are decompressed by `decoder`, the size limit then applies to the decompressed body too,
+    /// which can't go over `max_decoded_size` even if the size limit is higher
+    pub(crate) fn content_decoder(mut self, decoder: ContentDecoder, max_decoded_size: usize) -> Self {
+        let inner = self.inner_mut();
+        inner.content_decoder = Some(decoder);
+        inner.max_decoded_size = max_decoded_size;
+        self
+    }
 
-        inner.remaining -= read;
-        buf.truncate(read);
-        Ok(Some(buf))
+    /// The client sent `Expect: 100-continue`: `100 Continue` is written on the first read,
+    /// so that a handler answering without reading the body never asks the client for it.
+    pub(crate) fn expect_continue(mut self) -> Self {
+        self.inner_mut().expect_continue = true;
+        self
+    }
+
+    /// The request's `Content-Type`, checked by `json`
+    #[cfg(feature = "json")]
+    pub(crate) fn content_type(mut self, content_type: Option<HeaderValue>) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    #[cfg(feature = "json")]
+    pub(crate) fn max_json_size(mut self, max: usize) -> Self {
+        self.max_json_size = max;
+        self
+    }
+
+    /// True when the client is still waiting for `100 Continue` before sending a non-empty body
+    pub(crate) fn is_continue_pending(&mut self) -> bool {
+        let inner = self.inner_mut();
+        inner.expect_continue && !inner.is_finished()
+    }
+
+    pub fn into_io(self) -> TcpIO {
+        self.inner.into_inner().unwrap_or_else(PoisonError::into_inner).io.expect("HTTP/2 bodies are never turned back into a connection")
+    }
+
+    /// Next piece of the body, `None` once it has been read to the end
+    pub async fn next(&self) -> io::Result<Option<Bytes>> {
+        poll_fn(|cx| {
+            let limit = self.max_size.load(Ordering::Relaxed);
+            self.lock().poll_next_chunk(cx, limit)
+        })
+        .await
+    }
+
+    /// The body as a `tokio::io::AsyncBufRead`, e.g. to read it line by line:
+    /// ```rust,no_run
+    /// # use tokio::io::AsyncBufReadExt;
+    /// # async fn print_lines(payload: &http_tokio::BodyReader) -> std::io::Result<()> {
+    /// let mut lines = payload.buffered().lines();
+    /// while let Some(line) = lines.next_line().await? {
+    ///     println!("{line}");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn buffered(&self) -> StreamReader<&Self, Bytes> {
+        StreamReader::new(self)
     }
 
     pub async fn read_all(&self) -> io::Result<Vec<u8>> {
+        self.read_all_limited(usize::MAX).await
+    }
+
+    /// Reads the whole body, failing like the body size limit past `limit` bytes
+    async fn read_all_limited(&self, limit: usize) -> io::Result<Vec<u8>> {
         let mut result = Vec::with_capacity(1024);
-        while let Some(chunk) = self.next().await? {
-            result.extend_from_slice(&chunk);
+        loop {
+            let chunk = poll_fn(|cx| {
+                let limit = self.max_size.load(Ordering::Relaxed).min(limit);
+                self.lock().poll_next_chunk(cx, limit)
+            })
+            .await?;
+            match chunk {
+                Some(chunk) => result.extend_from_slice(&chunk),
+                None => return Ok(result),
+            }
         }
-        Ok(result)
+    }
+
+    /// Reads the whole body and decodes it as `application/x-www-form-urlencoded`
+    pub async fn form(&self) -> io::Result<Query> {
+        Ok(Query::parse_bytes(&self.read_all().await?))
+    }
+
+    /// Reads the whole body and deserializes it from JSON.
+    ///
+    /// The request must have an `application/json` (or `+json`) content type, otherwise the error maps to
+    /// `415 Unsupported Media Type`; malformed JSON maps to `400 Bad Request` (see `RequestError::status_code`).
+    /// Bodies over `Limits::max_json_size` are answered with `413 Payload Too Large`.
+    #[cfg(feature = "json")]
+    pub async fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, RequestError> {
+        let content_type = self.content_type.as_ref().map(ToString::to_string).unwrap_or_default();
+        let (mime, _) = content_type.split_once(';').unwrap_or((&content_type, ""));
+        let mime = mime.trim().to_ascii_lowercase();
+        if mime != "application/json" && !(mime.starts_with("application/") && mime.ends_with("+json")) {
+            return Err(RequestError::UnsupportedMediaType(content_type));
+        }
+
+        let body = match self.read_all_limited(self.max_json_size).await {
+            Ok(body) => body,
+            Err(err) => return Err(match self.lock().exceeded_limit {
+                Some(limit) => RequestError::PayloadTooLarge(limit),
+                None => RequestError::Read(err),
+            }),
+        };
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Trailer fields sent after the last chunk of a chunked body.
+    ///
+    /// Only available once the body has been read to the end.
+    pub async fn trailers(&self) -> Option<Headers> {
+        self.lock().trailers.clone()
     }
 
     pub async fn drain(&self) -> io::Result<()> {
-        let mut buf = vec![0u8; 1024];
-        let mut inner = self.0.lock().await;
+        poll_fn(|cx| {
+            let limit = self.max_size.load(Ordering::Relaxed);
+            let mut inner = self.lock();
+            // the rest of the body is discarded, no need to decompress it
+            loop {
+                let len = ready!(inner.poll_fill_raw(cx, limit))?.len();
+                if len == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                inner.consume_raw(len);
+            }
+        })
+        .await
+    }
+
+    /// The lock is never held across an await point, only for the duration of a poll
+    fn lock(&self) -> MutexGuard<'_, InnerBodyReader> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn inner_mut(&mut self) -> &mut InnerBodyReader {
+        self.inner.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl InnerBodyReader {
+    fn is_finished(&self) -> bool {
+        match self.decoder {
+            Decoder::Length { remaining } => remaining == 0,
+            Decoder::Chunked(ref state) => matches!(state, ChunkedState::Done),
+            #[cfg(feature = "http2")]
+            Decoder::Http2 { done, ref data, .. } => done && data.is_empty(),
+        }
+    }
+
+
+    /// Body bytes available, decompressed when a content decoder is set; empty at the end of the body
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<&[u8]>> {
+        if self.content_decoder.is_none() {
+            return self.poll_fill_raw(cx, limit);
+        }
+        let mut input = [0; DECODE_INPUT_LEN];
+        while self.decoded.is_empty() && !self.content_done {
+            let raw = ready!(self.poll_fill_raw(cx, limit))?;
+            let len = raw.len().min(input.len());
+            input[..len].copy_from_slice(&raw[..len]);
+            self.consume_raw(len);
+
+            let Some(decoder) = &mut self.content_decoder else { break };
+            self.content_done = len == 0;
+            let decoded_limit = limit.min(self.max_decoded_size);
+            let result = match len {
+                0 => decoder.finish(decoded_limit),
+                _ => decoder.write(&input[..len], decoded_limit),
+            };
+            let output = decoder.output();
+            if output.exceeded {
+                self.exceeded_limit = Some(decoded_limit);
+                return Poll::Ready(Err(too_large(decoded_limit)));
+            }
+            result.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            self.decoded = Bytes::from(std::mem::take(&mut output.buf));
+        }
+        Poll::Ready(Ok(&self.decoded))
+    }
+
+    /// Body bytes, still content-encoded, available in the connection's read buffer; empty at the end of the body
+    fn poll_fill_raw(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<&[u8]>> {
+        if let Some(limit) = self.exceeded_limit {
+            return Poll::Ready(Err(too_large(limit)));
+        }
         loop {
-            if inner.remaining == 0 {
-                break;
+            // a declared Content-Length or chunk size is checked before reading any of it
+            let next_size = match self.decoder {
+                Decoder::Length { remaining } | Decoder::Chunked(ChunkedState::Data { remaining }) => remaining,
+                Decoder::Chunked(_) => 0,
+                #[cfg(feature = "http2")]
+                Decoder::Http2 { ref data, .. } => data.len(),
+            };
+            if self.received.saturating_add(next_size) > limit {
+                self.exceeded_limit = Some(limit);
+                return Poll::Ready(Err(too_large(limit)));
+            }
+            if self.expect_continue && !self.is_finished() {
+                ready!(self.poll_send_continue(cx))?;
+            }
+
+            match &mut self.decoder {
+                Decoder::Length { remaining: 0 } | Decoder::Chunked(ChunkedState::Done) => return Poll::Ready(Ok(&[])),
+                Decoder::Length { remaining } | Decoder::Chunked(ChunkedState::Data { remaining }) => {
+                    let remaining = *remaining;
+                    let buf = ready!(Pin::new(connection(&mut self.io).reader()).poll_fill_buf(cx))?;
+                    if buf.is_empty() {
+                        return Poll::Ready(Err(unexpected_eof()));
+                    }
+                    return Poll::Ready(Ok(&buf[..buf.len().min(remaining)]));
+                }
+                Decoder::Chunked(ChunkedState::Invalid) => return Poll::Ready(Err(invalid_chunk("invalid chunked framing"))),
+                Decoder::Chunked(_) => {
+                    // after a framing error the rest of the connection can't be told apart from the body
+                    if let Err(err) = ready!(self.poll_chunk_line(cx)) {
+                        self.decoder = Decoder::Chunked(ChunkedState::Invalid);
+                        return Poll::Ready(Err(err));
+                    }
+                }
+                #[cfg(feature = "http2")]
+                Decoder::Http2 { stream, data, done } => {
+                    if !data.is_empty() || *done {
+                        let Decoder::Http2 { data, .. } = &self.decoder else { unreachable!() };
+                        return Poll::Ready(Ok(data));
+                    }
+                    match ready!(stream.poll_data(cx)) {
+                        // the limit is checked against the frame at the top of the loop
+                        Some(frame) => *data = frame.map_err(io::Error::other)?,
+                        None => {
+                            let trailers = ready!(stream.poll_trailers(cx)).map_err(io::Error::other)?;
+                            self.trailers = trailers.map(|trailers| {
+                                let mut headers = Headers::new();
+                                for (name, value) in &trailers {
+                                    headers.append_value(name.as_str().into(), HeaderValue::from_bytes(Bytes::copy_from_slice(value.as_bytes())));
+                                }
+                                headers
+                            });
+                            *done = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self.content_decoder {
+            Some(_) => self.decoded.advance(amt),
+            None => self.consume_raw(amt),
+        }
+    }
+
+    fn consume_raw(&mut self, amt: usize) {
+        match &mut self.decoder {
+            Decoder::Length { remaining } => *remaining -= amt,
+            Decoder::Chunked(ChunkedState::Data { remaining }) => {
+                *remaining -= amt;
+                if *remaining == 0 {
+                    self.decoder = Decoder::Chunked(ChunkedState::DataEnd);
+                }
+            }
+            #[cfg(feature = "http2")]
+            Decoder::Http2 { stream, data, .. } => {
+                data.advance(amt);
+                // lets the client send more of the body (RFC 9113 6.9)
+                let _ = stream.flow_control().release_capacity(amt);
+                self.received += amt;
+                return;
             }
+            _ => debug_assert_eq!(amt, 0),
+        }
+        self.received += amt;
+        Pin::new(connection(&mut self.io).reader()).consume(amt);
+    }
+
+    /// Copies the available bytes out of the read buffer, `None` at the end of the body
+    fn poll_next_chunk(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<Option<Bytes>>> {
+        #[cfg(feature = "http2")]
+        let shared = self.content_decoder.is_some() || matches!(self.decoder, Decoder::Http2 { .. });
+        #[cfg(not(feature = "http2"))]
+        let shared = self.content_decoder.is_some();
+        let buf = ready!(self.poll_fill_buf(cx, limit))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(None));
+        }
+        let chunk = match shared {
+            // the whole of `decoded` or of the HTTP/2 frame was returned, it's handed out as is
+            true => self.shared_chunk(),
+            false => Bytes::copy_from_slice(buf),
+        };
+        self.consume(chunk.len());
+        Poll::Ready(Ok(Some(chunk)))
+    }
+
+    /// Bytes returned by `poll_fill_buf` that are already held in a `Bytes`
+    fn shared_chunk(&self) -> Bytes {
+        match &self.decoder {
+            _ if self.content_decoder.is_some() => self.decoded.clone(),
+            #[cfg(feature = "http2")]
+            Decoder::Http2 { data, .. } => data.clone(),
+            _ => unreachable!("HTTP/1 bytes are in the connection's read buffer"),
+        }
+    }
 
-            let to_read = 1024.min(inner.remaining);
-            let read = inner.io.reader().read(&mut buf[..to_read]).await?;
-            if read == 0 {
-                break;
+    fn poll_read(&mut self, cx: &mut Context<'_>, limit: usize, out: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let buf = ready!(self.poll_fill_buf(cx, limit))?;
+        let len = buf.len().min(out.remaining());
+        out.put_slice(&buf[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_send_continue(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.continue_written < CONTINUE.len() {
+            let written = ready!(Pin::new(connection(&mut self.io).writer()).poll_write(cx, &CONTINUE[self.continue_written..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
             }
-            inner.remaining -= read;
+            self.continue_written += written;
         }
-        Ok(())
+        ready!(Pin::new(connection(&mut self.io).writer()).poll_flush(cx))?;
+        self.expect_continue = false;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads a chunk-size line, the CRLF after chunk data or a trailer line, and moves to the next state
+    fn poll_chunk_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let line = ready!(self.poll_line(cx))?;
+        match &mut self.decoder {
+            Decoder::Chunked(ChunkedState::Size) => {
+                let size = parse_chunk_size(&line)?;
+                self.decoder = Decoder::Chunked(match size {
+                    0 => ChunkedState::Trailers { count: 0, trailers: Headers::new() },
+                    _ => ChunkedState::Data { remaining: size },
+                });
+            }
+            Decoder::Chunked(ChunkedState::DataEnd) => {
+                // every chunk's data is followed by CRLF
+                if !line.is_empty() {
+                    return Poll::Ready(Err(invalid_chunk("missing CRLF after chunk data")));
+                }
+                self.decoder = Decoder::Chunked(ChunkedState::Size);
+            }
+            Decoder::Chunked(ChunkedState::Trailers { count, trailers }) => {
+                if line.is_empty() {
+                    self.trailers = Some(std::mem::take(trailers));
+                    self.decoder = Decoder::Chunked(ChunkedState::Done);
+                    return Poll::Ready(Ok(()));
+                }
+                *count += 1;
+                if *count > MAX_TRAILERS {
+                    return Poll::Ready(Err(invalid_chunk("too many trailer fields")));
+                }
+                // trailers are field lines like the header section, with the same rules
+                let line = Bytes::from(line);
+                let (name, value) = parse_field_line(&line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                trailers.append_value(HeaderName::from_token(line.slice_ref(name)), HeaderValue::from_bytes(line.slice_ref(value)));
+            }
+            _ => unreachable!("only called in chunk line states"),
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads a line and strips the CRLF terminator, a bare LF is rejected
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Vec<u8>>> {
+        loop {
+            let reader = connection(&mut self.io).reader();
+            let buf = ready!(Pin::new(&mut *reader).poll_fill_buf(cx))?;
+            if buf.is_empty() {
+                return Poll::Ready(Err(unexpected_eof()));
+            }
+            let (take, line_done) = match buf.iter().position(|b| *b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            if self.line.len() + take > MAX_CHUNK_LINE_LEN {
+                return Poll::Ready(Err(invalid_chunk("chunk line too long")));
+            }
+            self.line.extend_from_slice(&buf[..take]);
+            Pin::new(reader).consume(take);
+            if line_done {
+                let mut line = std::mem::take(&mut self.line);
+                // peers that accept a bare LF here disagree with us on where the chunk ends
+                if !line.ends_with(b"\r\n") {
+                    return Poll::Ready(Err(invalid_chunk("chunk line not terminated by CRLF")));
+                }
+                line.truncate(line.len() - 2);
+                return Poll::Ready(Ok(line));
+            }
+        }
+    }
+}
+
+impl AsyncRead for BodyReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let limit = *this.max_size.get_mut();
+        this.inner_mut().poll_read(cx, limit, buf)
+    }
+}
+
+impl AsyncRead for &BodyReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let limit = self.max_size.load(Ordering::Relaxed);
+        self.lock().poll_read(cx, limit, buf)
+    }
+}
+
+impl AsyncBufRead for BodyReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let limit = *this.max_size.get_mut();
+        this.inner.get_mut().unwrap_or_else(PoisonError::into_inner).poll_fill_buf(cx, limit)
     }
-}
\ No newline at end of file
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().inner_mut().consume(amt)
+    }
+}
+
+impl Stream for BodyReader {
+    type Item = io::Result<Bytes>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let limit = *this.max_size.get_mut();
+        this.inner_mut().poll_next_chunk(cx, limit).map(Result::transpose)
+    }
+}
+
+impl Stream for &BodyReader {
+    type Item = io::Result<Bytes>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let limit = self.max_size.load(Ordering::Relaxed);
+        self.lock().poll_next_chunk(cx, limit).map(Result::transpose)
+    }
+}
+
+/// The connection an HTTP/1 body is read from, borrowed apart from the other fields
+fn connection(io: &mut Option<TcpIO>) -> &mut TcpIO {
+    io.as_mut().expect("HTTP/1 bodies are read from a connection")
+}
+
+/// `chunk-size [ chunk-ext ]`, extensions must follow the grammar but are ignored (RFC 9112 7.1.1)
+fn parse_chunk_size(line: &[u8]) -> io::Result<usize> {
+    let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if digits == 0 {
+        return Err(invalid_chunk("invalid chunk size"));
+    }
+    if !is_chunk_ext(&line[digits..]) {
+        return Err(invalid_chunk("invalid chunk extension"));
+    }
+    line[..digits]
+        .iter()
+        .try_fold(0usize, |size, b| size.checked_mul(16)?.checked_add((*b as char).to_digit(16)? as usize))
+        .ok_or_else(|| invalid_chunk("chunk size too large"))
+}
+
+/// `*( BWS ";" BWS chunk-ext-name [ BWS "=" BWS chunk-ext-val ] )`
+fn is_chunk_ext(mut ext: &[u8]) -> bool {
+    while !ext.is_empty() {
+        let Some(rest) = skip_bws(ext).strip_prefix(b";") else {
+            return false;
+        };
+        let rest = skip_bws(rest);
+        let name = token_len(rest);
+        if name == 0 {
+            return false;
+        }
+        ext = &rest[name..];
+        if let Some(value) = skip_bws(ext).strip_prefix(b"=") {
+            let value = skip_bws(value);
+            let len = match value.first() {
+                Some(b'"') => quoted_string_len(value),
+                _ => Some(token_len(value)).filter(|len| *len > 0),
+            };
+            let Some(len) = len else {
+                return false;
+            };
+            ext = &value[len..];
+        }
+    }
+    true
+}
+
+fn skip_bws(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().take_while(|b| matches!(b, b' ' | b'\t')).count();
+    &bytes[len..]
+}
+
+fn token_len(bytes: &[u8]) -> usize {
+    bytes.iter().take_while(|b| is_token(std::slice::from_ref(b))).count()
+}
+
+/// Length of the `quoted-string` at the start of `bytes`, including both quotes
+fn quoted_string_len(bytes: &[u8]) -> Option<usize> {
+    // qdtext and the escaped octet of a quoted-pair: HTAB, SP, VCHAR and obs-text
+    let is_text = |b: u8| b == b'\t' || (b' '..=b'~').contains(&b) || b >= 0x80;
+    let mut i = 1;
+    loop {
+        match *bytes.get(i)? {
+            b'"' => return Some(i + 1),
+            b'\\' if bytes.get(i + 1).is_some_and(|b| is_text(*b)) => i += 2,
+            b if b != b'\\' && is_text(b) => i += 1,
+            _ => return None,
+        }
+    }
+}
+
+fn invalid_chunk(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn too_large(limit: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, PayloadTooLarge(limit))
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the end of the body")
+}
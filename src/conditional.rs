@@ -0,0 +1,114 @@
+use std::time::SystemTime;
+use crate::{headers::HeaderValue, status_code::StatusCode, Method, Request, Response};
+
+/// Answers `304 Not Modified` to a `GET` or `HEAD` request whose cached representation is still current,
+/// according to the response's `ETag` and `Last-Modified` validators (RFC 9110 13.1.2, 13.1.3, 13.2.2).
+pub(crate) fn apply(req: &Request, res: &mut Response) {
+    if (req.method != Method::GET && req.method != Method::HEAD) || res.status.code != 200 {
+        return;
+    }
+    let if_none_match = req.headers.get_all("If-None-Match").map(|values| {
+        values.iter().filter_map(HeaderValue::to_str).collect::<Vec<_>>().join(",")
+    });
+    let not_modified = match if_none_match {
+        // If-Modified-Since is ignored when If-None-Match is present
+        Some(if_none_match) => if_none_match.trim() == "*" || res.headers.get("ETag").is_some_and(|etag| etag_matches(&if_none_match, etag)),
+        None => {
+            let if_modified_since = req.headers.get("If-Modified-Since").and_then(parse_date);
+            let last_modified = res.headers.get("Last-Modified").and_then(parse_date);
+            matches!((if_modified_since, last_modified), (Some(since), Some(modified)) if modified <= since)
+        },
+    };
+    if not_modified {
+        res.status = StatusCode::NOT_MODIFIED;
+        res.body = None;
+    }
+}
+
+/// Weak comparison of `etag` with every entity tag of the list: the opaque tags are equal, `W/` ignored
+fn etag_matches(list: &str, etag: &str) -> bool {
+    let etag = etag.trim().trim_start_matches("W/");
+    let mut rest = list;
+    // entity tags can contain commas, the list is split on the quotes
+    while let Some(start) = rest.find('"') {
+        let Some(len) = rest[start + 1..].find('"') else {
+            return false;
+        };
+        if &rest[start..start + len + 2] == etag {
+            return true;
+        }
+        rest = &rest[start + len + 2..];
+    }
+    false
+}
+
+fn parse_date(date: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(date.trim()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{extensions::Extensions, headers::Headers, uri::Uri, version::Version};
+    use super::*;
+
+    const MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request {
+            method,
+            path: "/".to_owned(),
+            uri: Uri::parse("/").unwrap(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            extensions: Extensions::new(),
+            body: None,
+        };
+        for &header in headers {
+            req.headers.append(header);
+        }
+        req
+    }
+
+    /// Status of the response carrying `etag` and `Last-Modified: MODIFIED` to a `method` request with `headers`
+    fn status(method: Method, headers: &[(&str, &str)], etag: &str) -> u16 {
+        let mut res = Response::build().header(("ETag", etag)).header(("Last-Modified", MODIFIED)).body("content");
+        apply(&request(method, headers), &mut res);
+        if res.status.code == 304 {
+            assert!(res.body.is_none());
+        }
+        res.status.code
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        for if_none_match in ["\"v1\"", "W/\"v1\"", "\"v0\", \"v1\"", "\"a,b\",W/\"v1\"", " * "] {
+            assert_eq!(status(Method::GET, &[("If-None-Match", if_none_match)], "\"v1\""), 304, "{if_none_match}");
+            assert_eq!(status(Method::GET, &[("If-None-Match", if_none_match)], "W/\"v1\""), 304, "{if_none_match}");
+        }
+        for if_none_match in ["\"v2\"", "\"v1", "v1", "\"a,\"v1\"\""] {
+            assert_eq!(status(Method::GET, &[("If-None-Match", if_none_match)], "\"v1\""), 200, "{if_none_match}");
+        }
+        assert_eq!(status(Method::GET, &[("If-None-Match", "\"v0\""), ("If-None-Match", "\"v1\"")], "\"v1\""), 304);
+    }
+
+    #[test]
+    fn if_modified_since_compares_dates() {
+        assert_eq!(status(Method::GET, &[("If-Modified-Since", MODIFIED)], "\"v1\""), 304);
+        assert_eq!(status(Method::HEAD, &[("If-Modified-Since", "Thu, 22 Oct 2015 07:28:00 GMT")], "\"v1\""), 304);
+        assert_eq!(status(Method::GET, &[("If-Modified-Since", "Tue, 20 Oct 2015 07:28:00 GMT")], "\"v1\""), 200);
+        assert_eq!(status(Method::GET, &[("If-Modified-Since", "yesterday")], "\"v1\""), 200);
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence() {
+        assert_eq!(status(Method::GET, &[("If-None-Match", "\"v2\""), ("If-Modified-Since", MODIFIED)], "\"v1\""), 200);
+    }
+
+    #[test]
+    fn only_applies_to_successful_get_and_head() {
+        assert_eq!(status(Method::POST, &[("If-None-Match", "\"v1\"")], "\"v1\""), 200);
+        let mut res = Response::build().status(StatusCode::NOT_FOUND).header(("ETag", "\"v1\"")).body("missing");
+        apply(&request(Method::GET, &[("If-None-Match", "\"v1\"")]), &mut res);
+        assert_eq!(res.status.code, 404);
+    }
+}
@@ -0,0 +1,362 @@
+use std::{io::{self, Write}, pin::Pin, task::{ready, Context, Poll}};
+use bytes::Bytes;
+use flate2::write::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+use tokio_stream::Stream;
+use crate::{body::Body, response::ResponseError, Request, Response};
+
+/// Response compression, negotiated for every request from its `Accept-Encoding` header.
+///
+/// Responses are compressed when their content type is textual (`text/*`, JSON, JavaScript, XML, SVG...),
+/// they have no `Content-Encoding` yet and `Cache-Control: no-transform` isn't set.
+/// Fixed-length bodies get a new `Content-Length`, streams are compressed chunk by chunk and sent chunked.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::{server::Server, BodyReader, Compression, Request, Response};
+/// # async fn handler(_req: &Request, _body: &BodyReader) -> Response {
+/// #     Response::build().body("hello")
+/// # }
+/// # async fn run() -> std::io::Result<()> {
+/// Server::bind("0.0.0.0:8080").await?
+///     .compression(Compression::default().min_size(512))
+///     .serve(handler)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    min_size: usize,
+    level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self { min_size: 1024, level: 6 }
+    }
+}
+
+impl Compression {
+    /// Sets the size in bytes under which fixed-length bodies are sent uncompressed.
+    ///
+    /// Default is 1 KiB, streamed bodies are always compressed.
+    pub fn min_size(mut self, size: usize) -> Self {
+        self.min_size = size;
+        self
+    }
+
+    /// Sets the compression level, from 0 (fastest) to 9 (smallest); brotli quality goes up to 11.
+    ///
+    /// Default is 6.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Compresses `res` with the coding preferred by `req`, if any
+    pub(crate) fn apply(&self, req: &Request, res: &mut Response) {
+        let code = res.status.code;
+        if code < 200 || code == 204 || code == 206 || code == 304 {
+            return;
+        }
+        let headers = &res.headers;
+        if headers.contains_key("Content-Encoding") || headers.contains_key("Content-Range") || headers.contains_token("Cache-Control", "no-transform") {
+            return;
+        }
+        if !headers.get("Content-Type").is_some_and(is_compressible) {
+            return;
+        }
+        match &res.body {
+            Some(Body::Bytes(bytes)) if bytes.len() >= self.min_size => {},
+            Some(Body::File(file)) if file.len >= self.min_size as u64 => {},
+            Some(Body::Stream(_)) => {},
+            _ => return,
+        }
+
+        // the representation depends on Accept-Encoding even when it ends up uncompressed
+        if !res.headers.contains_token("Vary", "Accept-Encoding") && !res.headers.contains_token("Vary", "*") {
+            res.headers.append(("Vary", "Accept-Encoding"));
+        }
+        let Some(coding) = req.headers.get("Accept-Encoding").and_then(negotiate) else {
+            return;
+        };
+        let stream = match res.body.take() {
+            Some(Body::Bytes(bytes)) => {
+                let mut encoder = ContentEncoder::new(coding, self.level);
+                match encoder.write_all(&bytes).and_then(|_| encoder.finish()) {
+                    Ok(encoded) => {
+                        res.headers.insert(("Content-Length", encoded.len().to_string()));
+                        res.body = Some(Body::Bytes(encoded));
+                    },
+                    Err(_) => {
+                        res.body = Some(Body::Bytes(bytes));
+                        return;
+                    },
+                }
+                None
+            },
+            Some(Body::Stream(stream)) => Some(stream),
+            Some(Body::File(file)) => Some(file.into_stream()),
+            None => return,
+        };
+        if let Some(stream) = stream {
+            // the encoded length isn't known before the end of the stream
+            res.headers.remove("Content-Length");
+            res.headers.insert(("Transfer-Encoding", "chunked"));
+            res.body = Some(Body::Stream(Box::new(EncodedStream { inner: stream, encoder: Some(ContentEncoder::new(coding, self.level)) })));
+        }
+        res.headers.insert(("Content-Encoding", coding.as_str()));
+
+        // a strong validator identifies the exact bytes sent, which the encoding changes (RFC 9110 8.8.3)
+        let etag = res.headers.get("ETag").filter(|etag| etag.starts_with('"')).map(|etag| format!("W/{etag}"));
+        if let Some(etag) = etag {
+            res.headers.insert(("ETag", etag));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Coding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    /// Supported codings, in order of preference when the client weighs them equally
+    const ALL: &'static [Coding] = &[
+        #[cfg(feature = "brotli")]
+        Coding::Brotli,
+        Coding::Gzip,
+        Coding::Deflate,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Supported coding with the highest non-zero q-value in `Accept-Encoding` (RFC 9110 12.5.3)
+fn negotiate(accept_encoding: &str) -> Option<Coding> {
+    let mut best: Option<(Coding, f32)> = None;
+    for &coding in Coding::ALL {
+        let Some(q) = quality(accept_encoding, coding.as_str()) else { continue };
+        if q > 0.0 && best.is_none_or(|(_, best)| q > best) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// q-value given to `coding` by an `Accept-Encoding` value, codings that aren't listed
+/// get the q-value of `*` if present, `None` otherwise
+pub(crate) fn quality(accept_encoding: &str, coding: &str) -> Option<f32> {
+    let mut wildcard = None;
+    for element in accept_encoding.split(',') {
+        let mut params = element.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q)))
+            .unwrap_or(0.0);
+        if name.eq_ignore_ascii_case(coding) || (coding == "gzip" && name.eq_ignore_ascii_case("x-gzip")) {
+            return Some(q);
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard
+}
+
+/// Textual media types, already compressed formats (images, video, archives) gain nothing
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(mime.as_str(), "application/json" | "application/javascript" | "application/xml" | "application/wasm" | "image/svg+xml")
+}
+
+/// Compressor for a response body, writing to an in-memory buffer drained after every write
+enum ContentEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl ContentEncoder {
+    fn new(coding: Coding, level: u32) -> Self {
+        let flate_level = flate2::Compression::new(level.min(9));
+        match coding {
+            Coding::Gzip => ContentEncoder::Gzip(GzEncoder::new(Vec::new(), flate_level)),
+            Coding::Deflate => ContentEncoder::Deflate(ZlibEncoder::new(Vec::new(), flate_level)),
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => ContentEncoder::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, level.min(11), 22))),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            ContentEncoder::Gzip(encoder) => encoder,
+            ContentEncoder::Deflate(encoder) => encoder,
+            #[cfg(feature = "brotli")]
+            ContentEncoder::Brotli(encoder) => encoder.as_mut(),
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer().write_all(data)
+    }
+
+    /// Compresses `chunk` and flushes the encoder, so that the client can decode it without waiting for the next one
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        self.writer().write_all(chunk)?;
+        self.writer().flush()?;
+        let output = match self {
+            ContentEncoder::Gzip(encoder) => encoder.get_mut(),
+            ContentEncoder::Deflate(encoder) => encoder.get_mut(),
+            #[cfg(feature = "brotli")]
+            ContentEncoder::Brotli(encoder) => encoder.get_mut(),
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    /// Ends the compressed stream, returns the output not drained yet
+    fn finish(self) -> io::Result<Bytes> {
+        let output = match self {
+            ContentEncoder::Gzip(encoder) => encoder.finish()?,
+            ContentEncoder::Deflate(encoder) => encoder.finish()?,
+            #[cfg(feature = "brotli")]
+            ContentEncoder::Brotli(encoder) => encoder.into_inner(),
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+/// Response stream compressed on the fly
+struct EncodedStream {
+    inner: Box<dyn Stream<Item = Result<Bytes, ResponseError>> + Send + Sync + Unpin>,
+    /// `None` once the end of the compressed stream has been produced
+    encoder: Option<ContentEncoder>,
+}
+
+impl Stream for EncodedStream {
+    type Item = Result<Bytes, ResponseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let Some(encoder) = &mut this.encoder else {
+                return Poll::Ready(None);
+            };
+            let encoded = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(chunk)) => encoder.write_chunk(&chunk),
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => match this.encoder.take() {
+                    Some(encoder) => encoder.finish(),
+                    None => return Poll::Ready(None),
+                },
+            };
+            match encoded {
+                // an empty chunk would end a chunked body early
+                Ok(encoded) if encoded.is_empty() => continue,
+                Ok(encoded) => return Poll::Ready(Some(Ok(encoded))),
+                Err(err) => return Poll::Ready(Some(Err(err.into()))),
+            }
+        }
+    }
+}
+
+/// Decoder for a request body's `Content-Encoding`, fed with the encoded bytes as they arrive.
+///
+/// Decoded bytes are written to an `OutputBuf` that refuses to grow past a limit,
+/// so a small compressed body can't expand into an arbitrarily large allocation.
+pub(crate) enum ContentDecoder {
+    Gzip(GzDecoder<OutputBuf>),
+    /// `deflate` is the zlib format (RFC 9110 8.4.1.2)
+    Deflate(ZlibDecoder<OutputBuf>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::DecompressorWriter<OutputBuf>>),
+}
+
+/// Decoded bytes waiting to be read, `max` bounds their total size
+#[derive(Default)]
+pub(crate) struct OutputBuf {
+    pub buf: Vec<u8>,
+    written: usize,
+    max: usize,
+    pub exceeded: bool,
+}
+
+impl Write for OutputBuf {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.written + data.len() > self.max {
+            self.exceeded = true;
+            return Err(io::Error::other("decoded body exceeds the size limit"));
+        }
+        self.written += data.len();
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ContentDecoder {
+    /// Decoder for a `Content-Encoding` value, `Ok(None)` for `identity`.
+    ///
+    /// Only a single coding is supported, any other value is returned as the error.
+    pub(crate) fn new(encoding: &str) -> Result<Option<Self>, String> {
+        let output = OutputBuf::default();
+        Ok(Some(match encoding.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => return Ok(None),
+            "gzip" | "x-gzip" => ContentDecoder::Gzip(GzDecoder::new(output)),
+            "deflate" => ContentDecoder::Deflate(ZlibDecoder::new(output)),
+            #[cfg(feature = "brotli")]
+            "br" => ContentDecoder::Brotli(Box::new(brotli::DecompressorWriter::new(output, 4096))),
+            _ => return Err(encoding.to_string()),
+        }))
+    }
+
+    /// Decodes `input`, failing once the total decoded size would exceed `max`
+    pub(crate) fn write(&mut self, input: &[u8], max: usize) -> io::Result<()> {
+        self.output().max = max;
+        match self {
+            ContentDecoder::Gzip(decoder) => decoder.write_all(input).and_then(|_| decoder.flush()),
+            ContentDecoder::Deflate(decoder) => decoder.write_all(input).and_then(|_| decoder.flush()),
+            #[cfg(feature = "brotli")]
+            ContentDecoder::Brotli(decoder) => decoder.write_all(input).and_then(|_| decoder.flush()),
+        }
+    }
+
+    /// Called at the end of the encoded body, fails if the encoded data is truncated
+    pub(crate) fn finish(&mut self, max: usize) -> io::Result<()> {
+        self.output().max = max;
+        match self {
+            ContentDecoder::Gzip(decoder) => decoder.try_finish(),
+            ContentDecoder::Deflate(decoder) => decoder.try_finish(),
+            #[cfg(feature = "brotli")]
+            ContentDecoder::Brotli(decoder) => decoder.close(),
+        }
+    }
+
+    pub(crate) fn output(&mut self) -> &mut OutputBuf {
+        match self {
+            ContentDecoder::Gzip(decoder) => decoder.get_mut(),
+            ContentDecoder::Deflate(decoder) => decoder.get_mut(),
+            #[cfg(feature = "brotli")]
+            ContentDecoder::Brotli(decoder) => decoder.get_mut(),
+        }
+    }
+}
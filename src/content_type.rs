@@ -32,4 +32,5 @@ content_types! {
     (Plain, mime::TEXT_PLAIN_UTF_8),
     (OctetStream, mime::APPLICATION_OCTET_STREAM),
     (FormUrlEncoded, mime::APPLICATION_WWW_FORM_URLENCODED),
+    (EventStream, mime::TEXT_EVENT_STREAM),
 }
\ No newline at end of file
@@ -1,23 +1,32 @@
-use std::{collections::HashMap, ops::{Deref, DerefMut}};
+use std::{borrow::{Borrow, Cow}, collections::HashMap, fmt, hash::{Hash, Hasher}, ops::{Deref, DerefMut}};
+use bytes::Bytes;
 
 #[derive(Debug, Default, Clone)]
-pub struct Headers(HashMap<String, Vec<String>>);
+pub struct Headers(HashMap<HeaderName, Vec<HeaderValue>>);
 
 impl Headers {
     pub fn new() -> Self {
         Headers(HashMap::new())
     }
-    
-    /// Custom insert: forces lowercase keys
+
+    /// Custom insert: forces capitalized keys
     pub fn insert(&mut self, key_value: (impl AsRef<str>, impl AsRef<str>)) {
-        self.0.insert(capitalize_key(key_value.0.as_ref()), vec![key_value.1.as_ref().to_string()]);
+        self.insert_value(HeaderName::from(key_value.0.as_ref()), HeaderValue::from(key_value.1.as_ref()));
     }
 
     pub fn append(&mut self, key_value: (impl AsRef<str>, impl AsRef<str>)) {
+        self.append_value(HeaderName::from(key_value.0.as_ref()), HeaderValue::from(key_value.1.as_ref()));
+    }
+
+    pub fn insert_value(&mut self, key: HeaderName, value: HeaderValue) {
+        self.0.insert(key, vec![value]);
+    }
+
+    pub fn append_value(&mut self, key: HeaderName, value: HeaderValue) {
         self.0
-            .entry(capitalize_key(key_value.0.as_ref()))
+            .entry(key)
             .or_insert_with(Vec::new)
-            .push(key_value.1.as_ref().to_string());
+            .push(value);
     }
 
     pub fn insert_header_line(&mut self, line: &str) -> Result<(), ()> {
@@ -29,8 +38,30 @@ impl Headers {
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<&String> {
-        self.0.get(&capitalize_key(key)).map(|v| v.first()).flatten()
+    /// First value for `key`, `None` if missing or not valid UTF-8
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_value(key).and_then(HeaderValue::to_str)
+    }
+
+    /// First value for `key` as raw bytes
+    pub fn get_value(&self, key: &str) -> Option<&HeaderValue> {
+        self.get_all(key).and_then(|v| v.first())
+    }
+
+    /// All the values received for `key`, one per field line
+    pub fn get_all(&self, key: &str) -> Option<&Vec<HeaderValue>> {
+        self.0.get(canonicalize(key).as_ref())
+    }
+
+    /// Whether any comma-separated element of `key` equals `token`, ignoring case
+    /// (e.g. `Connection: keep-alive, Upgrade` contains `upgrade`)
+    pub fn contains_token(&self, key: &str, token: &str) -> bool {
+        self.get_all(key).is_some_and(|values| {
+            values.iter()
+                .filter_map(HeaderValue::to_str)
+                .flat_map(|value| value.split(','))
+                .any(|element| element.trim().eq_ignore_ascii_case(token))
+        })
     }
 
     pub fn add_set_cookie(&mut self, name: &str, value: &str) {
@@ -70,14 +101,33 @@ impl Headers {
             .join("\r\n")
     }
 
+    /// Writes every header line, each terminated by CRLF, keeping the values' raw bytes.
+    pub(crate) fn write_to(&self, buf: &mut Vec<u8>) {
+        for (key, values) in &self.0 {
+            for value in values {
+                buf.extend_from_slice(key.as_str().as_bytes());
+                buf.extend_from_slice(b": ");
+                buf.extend_from_slice(value.as_bytes());
+                buf.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+
+    /// True when `chunked` is the final transfer coding, e.g. `Transfer-Encoding: gzip, chunked`
     pub fn is_chunked(&self) -> bool {
-        self.get("Transfer-Encoding") == Some(&String::from("chunked"))
+        self.0.get("Transfer-Encoding")
+            .and_then(|values| values.last())
+            .and_then(HeaderValue::to_str)
+            .and_then(|value| value.rsplit(',').next())
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
     }
 }
 
-/// Allow Headers to behave like HashMap<String, String>
+/// Allow Headers to behave like HashMap<HeaderName, Vec<HeaderValue>>
+///
+/// Keys can be looked up with a `&str` in canonical form, e.g. `headers.contains_key("Content-Type")`.
 impl Deref for Headers {
-    type Target = HashMap<String, Vec<String>>;
+    type Target = HashMap<HeaderName, Vec<HeaderValue>>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -85,20 +135,161 @@ impl Deref for Headers {
 
 impl DerefMut for Headers {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0  
+        &mut self.0
     }
 }
 
-/// Capitalizes HTTP header keys (e.g., "content-type" -> "Content-Type").
-fn capitalize_key(key: &str) -> String {
-    key.split('-')
-        .map(|s| {
-            let mut chars = s.chars();
-            match chars.next() {
-                Some(first) => first.to_uppercase().chain(chars).collect(),
-                None => String::new(),
-            }
+/// Header name in canonical form (e.g. "Content-Type").
+///
+/// Names received already in canonical form share the request head buffer instead of being copied.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HeaderName(Bytes);
+
+impl HeaderName {
+    /// `name` must be a `token`, as checked by the request parser
+    pub(crate) fn from_token(name: Bytes) -> Self {
+        if is_canonical(&name) {
+            HeaderName(name)
+        } else {
+            HeaderName(Bytes::from(canonicalize_bytes(&name)))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // built either from a `&str` or from a token, and canonicalization only changes the case of ASCII letters
+        std::str::from_utf8(&self.0).expect("header names are valid UTF-8")
+    }
+}
+
+impl From<&str> for HeaderName {
+    fn from(name: &str) -> Self {
+        match canonicalize(name) {
+            Cow::Borrowed(name) => HeaderName(Bytes::copy_from_slice(name.as_bytes())),
+            Cow::Owned(name) => HeaderName(Bytes::from(name)),
+        }
+    }
+}
+
+/// Hashes like the `str` it borrows as, so that maps can be queried with a `&str`
+impl Hash for HeaderName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Borrow<str> for HeaderName {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for HeaderName {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Raw header value, not necessarily valid UTF-8.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HeaderValue(Bytes);
+
+impl HeaderValue {
+    pub fn from_bytes(value: impl Into<Bytes>) -> Self {
+        HeaderValue(value.into())
+    }
+
+    /// The value as a `&str`, `None` if it isn't valid UTF-8
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&str> for HeaderValue {
+    fn from(value: &str) -> Self {
+        HeaderValue(Bytes::copy_from_slice(value.as_bytes()))
+    }
+}
+
+impl From<String> for HeaderValue {
+    fn from(value: String) -> Self {
+        HeaderValue(Bytes::from(value))
+    }
+}
+
+impl PartialEq<str> for HeaderValue {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for HeaderValue {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+/// Lossy: invalid UTF-8 sequences are replaced with U+FFFD
+impl fmt::Display for HeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl fmt::Debug for HeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&String::from_utf8_lossy(&self.0), f)
+    }
+}
+
+/// `token` grammar from RFC 9110 5.6.2, used by field names and methods
+pub(crate) fn is_token(s: &[u8]) -> bool {
+    !s.is_empty() && s.iter().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+}
+
+/// Canonical form capitalizes the first letter of every dash-separated word
+/// and lowercases the rest (e.g. "content-TYPE" -> "Content-Type").
+fn is_canonical(key: &[u8]) -> bool {
+    let mut upper = true;
+    key.iter().all(|&b| {
+        let ok = if upper { !b.is_ascii_lowercase() } else { !b.is_ascii_uppercase() };
+        upper = b == b'-';
+        ok
+    })
+}
+
+fn canonicalize_bytes(key: &[u8]) -> Vec<u8> {
+    let mut upper = true;
+    key.iter()
+        .map(|&b| {
+            let c = if upper { b.to_ascii_uppercase() } else { b.to_ascii_lowercase() };
+            upper = b == b'-';
+            c
         })
-        .collect::<Vec<_>>()
-        .join("-")
+        .collect()
+}
+
+/// Capitalizes HTTP header keys (e.g., "content-type" -> "Content-Type"), only allocating when needed.
+fn canonicalize(key: &str) -> Cow<'_, str> {
+    if is_canonical(key.as_bytes()) {
+        Cow::Borrowed(key)
+    } else {
+        // only ASCII letters change case, so the result is still valid UTF-8
+        Cow::Owned(String::from_utf8(canonicalize_bytes(key.as_bytes())).unwrap_or_default())
+    }
 }
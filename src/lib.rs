@@ -3,15 +3,43 @@ mod body_reader;
 pub mod extensions;
 pub mod headers;
 pub mod content_type;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+mod content_encoding;
+mod conditional;
+mod limits;
+mod method;
+mod multipart;
 mod status_code;
+mod parser;
 mod request;
+mod query;
+mod range;
 mod response;
+mod sse;
 mod tcp_io;
+mod upgrade;
+mod uri;
+mod version;
 pub mod server;
 
-pub use tcp_io::TcpIO;
+pub use tcp_io::{ReadHalf, TcpIO, WriteHalf};
 pub use request::{IncomingRequest as Request, RequestError};
 pub use status_code::StatusCode;
+pub use uri::{Uri, UriError, UriForm};
+pub use query::Query;
+pub use limits::Limits;
+pub use content_encoding::Compression;
+pub use version::Version;
+pub use method::{InvalidMethod, Method};
 pub use response::{HttpResponse as Response, ResponseError};
-pub use body_reader::BodyReader;
-pub use server::run_server;
\ No newline at end of file
+pub use upgrade::{OnUpgrade, UpgradeError};
+pub use body_reader::{BodyReader, PayloadTooLarge};
+pub use multipart::{Multipart, MultipartError, Part};
+pub use sse::{Event, EventStream};
+pub use server::run_server;
+/// Runs `future` to completion, tests of async code have no runtime of their own
+#[cfg(test)]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().expect("test runtime").block_on(future)
+}
@@ -0,0 +1,96 @@
+/// Size limits enforced while receiving a request.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::Limits;
+/// let limits = Limits::default()
+///     .max_request_line_len(4 * 1024)
+///     .max_headers(50);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub(crate) max_request_line_len: usize,
+    pub(crate) max_header_len: usize,
+    pub(crate) max_headers: usize,
+    pub(crate) max_head_len: usize,
+    pub(crate) max_body_size: Option<usize>,
+    pub(crate) max_decompressed_size: usize,
+    #[cfg(feature = "json")]
+    pub(crate) max_json_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line_len: 8 * 1024,
+            max_header_len: 8 * 1024,
+            max_headers: 100,
+            max_head_len: 64 * 1024,
+            max_body_size: None,
+            max_decompressed_size: 8 * 1024 * 1024,
+            #[cfg(feature = "json")]
+            max_json_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Sets the maximum length in bytes of the request line, line terminator included.
+    ///
+    /// Default is 8 KiB, exceeding it results in `414 URI Too Long`.
+    pub fn max_request_line_len(mut self, len: usize) -> Self {
+        self.max_request_line_len = len;
+        self
+    }
+
+    /// Sets the maximum length in bytes of a single header line, line terminator included.
+    ///
+    /// Default is 8 KiB, exceeding it results in `431 Request Header Fields Too Large`.
+    pub fn max_header_len(mut self, len: usize) -> Self {
+        self.max_header_len = len;
+        self
+    }
+
+    /// Sets the maximum number of header lines.
+    ///
+    /// Default is 100 headers, exceeding it results in `431 Request Header Fields Too Large`.
+    pub fn max_headers(mut self, count: usize) -> Self {
+        self.max_headers = count;
+        self
+    }
+
+    /// Sets the maximum size in bytes of the whole request head (request line and headers).
+    ///
+    /// Default is 64 KiB, exceeding it results in `431 Request Header Fields Too Large`.
+    pub fn max_head_len(mut self, len: usize) -> Self {
+        self.max_head_len = len;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a request body, handlers can override it with `BodyReader::set_max_size`.
+    ///
+    /// Default is no limit, exceeding it results in `413 Payload Too Large`,
+    /// sent without calling the handler when the request declares a larger `Content-Length`.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = Some(size);
+        self
+    }
+
+    /// Sets the maximum size in bytes of a request body once decompressed, on top of `max_body_size`,
+    /// when request decompression is enabled and the body has a `Content-Encoding`.
+    ///
+    /// Default is 8 MiB, exceeding it results in `413 Payload Too Large`.
+    pub fn max_decompressed_size(mut self, size: usize) -> Self {
+        self.max_decompressed_size = size;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a body read with `BodyReader::json`, on top of `max_body_size`.
+    ///
+    /// Default is 1 MiB, exceeding it results in `413 Payload Too Large`.
+    #[cfg(feature = "json")]
+    pub fn max_json_size(mut self, size: usize) -> Self {
+        self.max_json_size = size;
+        self
+    }
+}
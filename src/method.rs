@@ -0,0 +1,142 @@
+use std::{fmt::{self, Display}, str::FromStr};
+
+use crate::headers::is_token;
+
+/// Request method.
+///
+/// Methods are case-sensitive (RFC 9110 9.1): `get` is an extension method, not `GET`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Method(Inner);
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid method: {0:?}")]
+pub struct InvalidMethod(pub String);
+
+impl Method {
+    /// Parses a method token, unknown tokens become extension methods
+    pub fn from_bytes(method: &[u8]) -> Result<Self, InvalidMethod> {
+        if let Some(method) = Self::standard(method) {
+            return Ok(method);
+        }
+        match std::str::from_utf8(method) {
+            Ok(method) if is_token(method.as_bytes()) => Ok(Method(Inner::Extension(method.into()))),
+            _ => Err(InvalidMethod(String::from_utf8_lossy(method).into_owned())),
+        }
+    }
+
+    pub fn is_extension(&self) -> bool {
+        matches!(self.0, Inner::Extension(_))
+    }
+}
+
+macro_rules! methods {
+    (
+        $(
+            $(#[$docs:meta])*
+            ($konst:ident, $variant:ident, $name:expr, safe: $safe:expr, idempotent: $idempotent:expr);
+        )+
+    ) => {
+        #[derive(Clone, PartialEq, Eq, Hash)]
+        enum Inner {
+            $($variant,)+
+            Extension(Box<str>),
+        }
+
+        impl Method {
+            $(
+                $(#[$docs])*
+                pub const $konst: Method = Method(Inner::$variant);
+            )+
+
+            pub fn as_str(&self) -> &str {
+                match &self.0 {
+                    $(Inner::$variant => $name,)+
+                    Inner::Extension(method) => method,
+                }
+            }
+
+            /// Safe methods are read-only (RFC 9110 9.2.1), extension methods are never considered safe
+            pub fn is_safe(&self) -> bool {
+                match self.0 {
+                    $(Inner::$variant => $safe,)+
+                    Inner::Extension(_) => false,
+                }
+            }
+
+            /// Idempotent methods can be retried automatically (RFC 9110 9.2.2), extension methods are never considered idempotent
+            pub fn is_idempotent(&self) -> bool {
+                match self.0 {
+                    $(Inner::$variant => $idempotent,)+
+                    Inner::Extension(_) => false,
+                }
+            }
+
+            fn standard(method: &[u8]) -> Option<Self> {
+                $(
+                    if method == $name.as_bytes() {
+                        return Some(Method::$konst);
+                    }
+                )+
+                None
+            }
+        }
+    };
+}
+
+methods! {
+    /// **GET**: Transfers a current representation of the target resource.
+    (GET, Get, "GET", safe: true, idempotent: true);
+    /// **HEAD**: Same as GET, but the server does not send the response content.
+    (HEAD, Head, "HEAD", safe: true, idempotent: true);
+    /// **POST**: Performs resource-specific processing on the request content.
+    (POST, Post, "POST", safe: false, idempotent: false);
+    /// **PUT**: Replaces all current representations of the target resource with the request content.
+    (PUT, Put, "PUT", safe: false, idempotent: true);
+    /// **DELETE**: Removes all current representations of the target resource.
+    (DELETE, Delete, "DELETE", safe: false, idempotent: true);
+    /// **CONNECT**: Establishes a tunnel to the server identified by the target resource.
+    (CONNECT, Connect, "CONNECT", safe: false, idempotent: false);
+    /// **OPTIONS**: Describes the communication options for the target resource.
+    (OPTIONS, Options, "OPTIONS", safe: true, idempotent: true);
+    /// **TRACE**: Performs a message loop-back test along the path to the target resource.
+    (TRACE, Trace, "TRACE", safe: true, idempotent: true);
+    /// **PATCH (RFC 5789)**: Applies partial modifications to the target resource.
+    (PATCH, Patch, "PATCH", safe: false, idempotent: false);
+}
+
+impl FromStr for Method {
+    type Err = InvalidMethod;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Method::from_bytes(s.as_bytes())
+    }
+}
+
+impl AsRef<str> for Method {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq<str> for Method {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Method {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,439 @@
+use std::{io, path::Path};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::{fs::File, io::AsyncWriteExt};
+use crate::{headers::Headers, BodyReader, Request};
+
+/// Longest header section accepted for a single part
+const MAX_PART_HEAD_LEN: usize = 8 * 1024;
+
+/// Streaming `multipart/form-data` parser (RFC 7578) over a request body.
+///
+/// Parts are yielded one at a time and their content is read incrementally, nothing is buffered
+/// beyond what the body reader returns; moving to the next part skips what is left of the current one.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use std::path::Path;
+/// # use http_tokio::{BodyReader, Multipart, Request};
+/// # async fn upload(req: &Request, payload: &BodyReader) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut multipart = Multipart::from_request(req, payload)?.max_parts(10).max_part_size(10 * 1024 * 1024);
+/// while let Some(part) = multipart.next_part().await? {
+///     // only the last component of the client-supplied name is kept
+///     match part.filename().and_then(|name| Path::new(name).file_name()).map(ToOwned::to_owned) {
+///         Some(filename) => { part.save_to(Path::new("uploads").join(filename)).await?; },
+///         None => {
+///             let name = part.name().unwrap_or_default().to_owned();
+///             println!("{name} = {:?}", String::from_utf8_lossy(&part.bytes().await?));
+///         },
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Multipart<'a> {
+    body: &'a BodyReader,
+    /// `CRLF--boundary`, the CRLF belongs to the delimiter and not to the preceding part
+    delimiter: Vec<u8>,
+    buf: BytesMut,
+    state: State,
+    parts: usize,
+    max_parts: usize,
+    max_part_size: usize,
+}
+
+enum State {
+    Preamble,
+    Body { read: usize },
+    /// right after a delimiter, either a new part or the closing `--`
+    Delimiter,
+    Done,
+}
+
+impl<'a> Multipart<'a> {
+    pub fn new(body: &'a BodyReader, boundary: impl AsRef<str>) -> Self {
+        Multipart {
+            body,
+            delimiter: [b"\r\n--", boundary.as_ref().as_bytes()].concat(),
+            // the first delimiter may start the body, without a CRLF before it
+            buf: BytesMut::from(&b"\r\n"[..]),
+            state: State::Preamble,
+            parts: 0,
+            max_parts: 100,
+            max_part_size: usize::MAX,
+        }
+    }
+
+    /// Parser for a request with a `multipart/form-data` content type, using its `boundary` parameter
+    pub fn from_request(req: &Request, body: &'a BodyReader) -> Result<Self, MultipartError> {
+        let boundary = req.headers.get("Content-Type").and_then(parse_boundary).ok_or(MultipartError::NotMultipart)?;
+        Ok(Self::new(body, boundary))
+    }
+
+    /// Sets the maximum number of parts, default is 100
+    pub fn max_parts(mut self, count: usize) -> Self {
+        self.max_parts = count;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a single part's content read through `Part`, default is no limit
+    /// other than the request body's own limit, which alone bounds the parts that are skipped
+    pub fn max_part_size(mut self, size: usize) -> Self {
+        self.max_part_size = size;
+        self
+    }
+
+    /// Next part, `None` after the closing boundary
+    pub async fn next_part(&mut self) -> Result<Option<Part<'_, 'a>>, MultipartError> {
+        // skip the preamble or the unread content of the previous part
+        while matches!(self.state, State::Preamble | State::Body { .. }) {
+            self.next_chunk().await?;
+        }
+        if let State::Done = self.state {
+            return Ok(None);
+        }
+
+        while self.buf.len() < 2 {
+            self.fill().await?;
+        }
+        // the epilogue after the close delimiter is ignored
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        // transport padding is allowed between the boundary and its CRLF
+        let line = self.line(MAX_PART_HEAD_LEN).await?;
+        if !line.iter().all(|b| *b == b' ' || *b == b'\t') {
+            return Err(MultipartError::InvalidBoundary);
+        }
+
+        self.parts += 1;
+        if self.parts > self.max_parts {
+            return Err(MultipartError::TooManyParts(self.max_parts));
+        }
+
+        let mut headers = Headers::new();
+        let mut head_len = 0;
+        loop {
+            let max_len = MAX_PART_HEAD_LEN.checked_sub(head_len).ok_or(MultipartError::HeadersTooLarge)?;
+            let line = self.line(max_len).await?;
+            head_len += line.len() + 2;
+            if line.is_empty() {
+                break;
+            }
+            let line = String::from_utf8_lossy(&line);
+            match line.split_once(':') {
+                Some((key, value)) if !key.is_empty() && !key.ends_with([' ', '\t']) => headers.append((key, value.trim())),
+                _ => return Err(MultipartError::InvalidHeader(line.into_owned())),
+            }
+        }
+
+        let disposition = headers.get("Content-Disposition").unwrap_or_default();
+        let name = disposition_param(disposition, "name");
+        let filename = disposition_param(disposition, "filename");
+        self.state = State::Body { read: 0 };
+        Ok(Some(Part { multipart: self, headers, name, filename }))
+    }
+
+    /// Content up to the next delimiter, `None` once it is reached
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        loop {
+            if let Some(i) = self.buf.windows(self.delimiter.len()).position(|w| w == self.delimiter) {
+                if i > 0 {
+                    return Ok(Some(self.buf.split_to(i).freeze()));
+                }
+                self.buf.advance(self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(None);
+            }
+            // keep the tail that could be the beginning of a delimiter split across reads
+            let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                return Ok(Some(self.buf.split_to(safe).freeze()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Reads a CRLF terminated line of at most `max_len` bytes and strips the CRLF
+    async fn line(&mut self, max_len: usize) -> Result<Bytes, MultipartError> {
+        loop {
+            if let Some(i) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                // a whole line may arrive in one chunk, its length is checked all the same
+                if i > max_len {
+                    return Err(MultipartError::HeadersTooLarge);
+                }
+                let line = self.buf.split_to(i).freeze();
+                self.buf.advance(2);
+                return Ok(line);
+            }
+            if self.buf.len() > max_len {
+                return Err(MultipartError::HeadersTooLarge);
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), MultipartError> {
+        match self.body.next().await? {
+            Some(chunk) => self.buf.extend_from_slice(&chunk),
+            None => return Err(MultipartError::UnexpectedEnd),
+        }
+        Ok(())
+    }
+}
+
+/// A single part of a multipart body, borrowing the parser until it is dropped.
+pub struct Part<'m, 'a> {
+    multipart: &'m mut Multipart<'a>,
+    headers: Headers,
+    name: Option<String>,
+    filename: Option<String>,
+}
+
+impl Part<'_, '_> {
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Form field name from `Content-Disposition`
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Client-supplied file name from `Content-Disposition`, not sanitized: don't use it as a path as is
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Part's `Content-Type`, `text/plain` when missing as RFC 7578 defines
+    pub fn content_type(&self) -> &str {
+        self.headers.get("Content-Type").unwrap_or("text/plain")
+    }
+
+    /// Next piece of the part's content, `None` at the end of the part
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        if !matches!(self.multipart.state, State::Body { .. }) {
+            return Ok(None);
+        }
+        let chunk = self.multipart.next_chunk().await?;
+        if let (Some(chunk), State::Body { read }) = (&chunk, &mut self.multipart.state) {
+            *read += chunk.len();
+            if *read > self.multipart.max_part_size {
+                return Err(MultipartError::PartTooLarge(self.multipart.max_part_size));
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Reads the rest of the part's content into memory
+    pub async fn bytes(mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut content = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            content.extend_from_slice(&chunk);
+        }
+        Ok(content)
+    }
+
+    /// Streams the rest of the part's content to a new file at `path`, returns the number of bytes written
+    pub async fn save_to(mut self, path: impl AsRef<Path>) -> Result<u64, MultipartError> {
+        let mut file = File::create(path).await?;
+        let mut written = 0;
+        while let Some(chunk) = self.chunk().await? {
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+        Ok(written)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MultipartError {
+    #[error("multipart io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("content type is not multipart/form-data with a boundary")]
+    NotMultipart,
+
+    #[error("body ended before the closing boundary")]
+    UnexpectedEnd,
+
+    #[error("invalid boundary line")]
+    InvalidBoundary,
+
+    #[error("invalid part header: {0:?}")]
+    InvalidHeader(String),
+
+    #[error("part headers too large")]
+    HeadersTooLarge,
+
+    #[error("more than {0} parts")]
+    TooManyParts(usize),
+
+    #[error("part exceeds the limit of {0} bytes")]
+    PartTooLarge(usize),
+}
+
+/// `boundary` parameter of a `multipart/form-data` content type, 1 to 70 characters (RFC 2046 5.1.1)
+fn parse_boundary(content_type: &str) -> Option<String> {
+    let (mime, _) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    disposition_param(content_type, "boundary").filter(|b| (1..=70).contains(&b.len()))
+}
+
+/// Value of a `; key=value` parameter, either a token or a quoted string
+fn disposition_param(value: &str, key: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        let (name, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (param, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut param = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (_, '\\') => param.push(chars.next()?.1),
+                        (i, '"') => break i + 1,
+                        (_, c) => param.push(c),
+                    }
+                };
+                (param, &quoted[end..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim_end().to_string(), &after[end..])
+            }
+        };
+        if name.trim().eq_ignore_ascii_case(key) {
+            return Some(param);
+        }
+        rest = next.split_once(';')?.1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}};
+    use crate::{block_on, TcpIO};
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        value a\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"f\"; filename=\"dir\\\\f \\\"1\\\".txt\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        \r\n--Xy\r\n-XyZ\r\n\
+        --XyZ--\r\nepilogue";
+
+    /// Chunked body made of `chunks`, each of them is returned by its own read;
+    /// the client end is returned too so that reads don't hit EOF
+    async fn chunked_body(chunks: &[&[u8]]) -> (BodyReader, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        for chunk in chunks.iter().filter(|chunk| !chunk.is_empty()) {
+            client.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await.unwrap();
+            client.write_all(chunk).await.unwrap();
+            client.write_all(b"\r\n").await.unwrap();
+        }
+        client.write_all(b"0\r\n\r\n").await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (BodyReader::chunked(TcpIO::new(server)), client)
+    }
+
+    /// Name, file name, content type and content of every part
+    async fn parts(multipart: &mut Multipart<'_>) -> Result<Vec<(Option<String>, Option<String>, String, Vec<u8>)>, MultipartError> {
+        let mut parts = Vec::new();
+        while let Some(part) = multipart.next_part().await? {
+            let (name, filename) = (part.name().map(str::to_owned), part.filename().map(str::to_owned));
+            let content_type = part.content_type().to_owned();
+            parts.push((name, filename, content_type, part.bytes().await?));
+        }
+        Ok(parts)
+    }
+
+    #[test]
+    fn parses_parts() {
+        block_on(async {
+            let (body, _client) = chunked_body(&[BODY]).await;
+            let parts = parts(&mut Multipart::new(&body, "XyZ")).await.unwrap();
+            assert_eq!(parts.len(), 2);
+            assert_eq!(parts[0], (Some("a".to_owned()), None, "text/plain".to_owned(), b"value a".to_vec()));
+            assert_eq!(parts[1].0.as_deref(), Some("f"));
+            assert_eq!(parts[1].1.as_deref(), Some("dir\\f \"1\".txt"));
+            assert_eq!(parts[1].2, "application/octet-stream");
+            assert_eq!(parts[1].3, b"\r\n--Xy\r\n-XyZ");
+        });
+    }
+
+    #[test]
+    fn finds_delimiters_split_across_reads() {
+        block_on(async {
+            let (body, _client) = chunked_body(&[BODY]).await;
+            let expected = parts(&mut Multipart::new(&body, "XyZ")).await.unwrap();
+            for i in 1..BODY.len() {
+                let (body, _client) = chunked_body(&[&BODY[..i], &BODY[i..]]).await;
+                assert_eq!(parts(&mut Multipart::new(&body, "XyZ")).await.unwrap(), expected, "split at {i}");
+            }
+            let bytes = BODY.chunks(1).collect::<Vec<_>>();
+            let (body, _client) = chunked_body(&bytes).await;
+            assert_eq!(parts(&mut Multipart::new(&body, "XyZ")).await.unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn part_size_limits_reads_but_not_skipping() {
+        block_on(async {
+            let (body, _client) = chunked_body(&[BODY]).await;
+            let mut multipart = Multipart::new(&body, "XyZ").max_part_size(4);
+            let part = multipart.next_part().await.unwrap().unwrap();
+            assert!(matches!(part.bytes().await, Err(MultipartError::PartTooLarge(4))));
+
+            let (body, _client) = chunked_body(&[BODY]).await;
+            let mut multipart = Multipart::new(&body, "XyZ").max_part_size(4);
+            multipart.next_part().await.unwrap().unwrap();
+            let part = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(part.name(), Some("f"));
+            drop(part);
+            assert!(multipart.next_part().await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn limits_the_number_of_parts() {
+        block_on(async {
+            let (body, _client) = chunked_body(&[BODY]).await;
+            let mut multipart = Multipart::new(&body, "XyZ").max_parts(1);
+            multipart.next_part().await.unwrap().unwrap();
+            assert!(matches!(multipart.next_part().await, Err(MultipartError::TooManyParts(1))));
+        });
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        block_on(async {
+            let (body, _client) = chunked_body(&[b"--XyZ\r\n\r\nvalue\r\n--XyZ"]).await;
+            let mut multipart = Multipart::new(&body, "XyZ");
+            multipart.next_part().await.unwrap().unwrap();
+            assert!(matches!(multipart.next_part().await, Err(MultipartError::UnexpectedEnd)));
+
+            let (body, _client) = chunked_body(&[b"--XyZ\r\nNo-Colon\r\n\r\n\r\n--XyZ--"]).await;
+            assert!(matches!(Multipart::new(&body, "XyZ").next_part().await, Err(MultipartError::InvalidHeader(_))));
+
+            let (body, _client) = chunked_body(&[b"--XyZx\r\n\r\n\r\n--XyZ--"]).await;
+            assert!(matches!(Multipart::new(&body, "XyZ").next_part().await, Err(MultipartError::InvalidBoundary)));
+        });
+    }
+
+    #[test]
+    fn parses_boundary_parameter() {
+        assert_eq!(parse_boundary("multipart/form-data; boundary=abc").as_deref(), Some("abc"));
+        assert_eq!(parse_boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b;c\"").as_deref(), Some("a b;c"));
+        assert_eq!(parse_boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(parse_boundary("multipart/form-data"), None);
+        assert_eq!(parse_boundary(&format!("multipart/form-data; boundary={}", "a".repeat(71))), None);
+    }
+}
@@ -0,0 +1,206 @@
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::{headers::{is_token, HeaderName, HeaderValue, Headers}, limits::Limits, RequestError};
+
+/// Request line and header fields, borrowing from a single head buffer
+pub(crate) struct RequestHead {
+    pub method: Bytes,
+    pub target: Bytes,
+    pub version: Bytes,
+    pub headers: Headers,
+}
+
+/// Reads the request head up to and including the empty line that terminates it.
+///
+/// Bytes are copied once from the reader's buffer into a single allocation;
+/// the limits are checked while reading so oversized heads are never fully buffered.
+pub(crate) async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R, limits: &Limits) -> Result<Bytes, RequestError> {
+    let mut head = BytesMut::new();
+    let mut line_start = 0;
+    let mut header_count = 0;
+    let mut in_request_line = true;
+
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Err(match head.is_empty() {
+                true => RequestError::ConnectionClosed,
+                false => RequestError::Read(std::io::ErrorKind::UnexpectedEof.into()),
+            });
+        }
+
+        let (take, line_done) = match buf.iter().position(|b| *b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (buf.len(), false),
+        };
+        head.extend_from_slice(&buf[..take]);
+        reader.consume(take);
+
+        // a line that is still incomplete must leave room for at least its terminator
+        let line_len = head.len() - line_start + !line_done as usize;
+        let head_len = head.len() + !line_done as usize;
+        if in_request_line && line_len > limits.max_request_line_len {
+            return Err(RequestError::RequestLineTooLong);
+        }
+        if !in_request_line && line_len > limits.max_header_len {
+            return Err(RequestError::HeaderTooLarge);
+        }
+        if head_len > limits.max_head_len {
+            return Err(RequestError::HeadTooLarge);
+        }
+        if !line_done {
+            continue;
+        }
+
+        let is_empty_line = matches!(&head[line_start..], b"\n" | b"\r\n");
+        match (is_empty_line, in_request_line) {
+            // empty lines received before the request line are ignored (RFC 9112 2.2)
+            (true, true) => head.clear(),
+            (true, false) => break,
+            (false, true) => in_request_line = false,
+            (false, false) => {
+                header_count += 1;
+                if header_count > limits.max_headers {
+                    return Err(RequestError::TooManyHeaders);
+                }
+            }
+        }
+        line_start = head.len();
+    }
+
+    Ok(head.freeze())
+}
+
+/// Splits a head returned by `read_head` into its request line and header fields,
+/// header names and values are slices of `head`.
+pub(crate) fn parse_head(head: Bytes) -> Result<RequestHead, RequestError> {
+    let mut lines = head
+        .split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .take_while(|line| !line.is_empty());
+
+    let request_line = lines.next().unwrap_or_default();
+    let invalid_request_line = || RequestError::InvalidRequestLine(String::from_utf8_lossy(request_line).into_owned());
+    let mut parts = request_line.split(|b| *b == b' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid_request_line());
+    };
+    if method.is_empty() || target.is_empty() || request_line.contains(&b'\r') {
+        return Err(invalid_request_line());
+    }
+
+    let mut headers = Headers::new();
+    for line in lines {
+        let (name, value) = parse_field_line(line)?;
+        headers.append_value(HeaderName::from_token(head.slice_ref(name)), HeaderValue::from_bytes(head.slice_ref(value)));
+    }
+
+    Ok(RequestHead {
+        method: head.slice_ref(method),
+        target: head.slice_ref(target),
+        version: head.slice_ref(version),
+        headers,
+    })
+}
+
+/// Splits a header or trailer field line into its name and value, without the optional whitespace around the value
+pub(crate) fn parse_field_line(line: &[u8]) -> Result<(&[u8], &[u8]), RequestError> {
+    let lossy = || String::from_utf8_lossy(line).into_owned();
+    if line.starts_with(b" ") || line.starts_with(b"\t") {
+        return Err(RequestError::ObsoleteLineFolding(lossy()));
+    }
+    let colon = line.iter().position(|b| *b == b':').ok_or_else(|| RequestError::InvalidHeader(lossy()))?;
+    let (name, value) = (&line[..colon], trim_ows(&line[colon + 1..]));
+    if name.ends_with(b" ") || name.ends_with(b"\t") {
+        return Err(RequestError::WhitespaceBeforeColon(lossy()));
+    }
+    // field-value allows VCHAR, obs-text, SP and HTAB only
+    if !is_token(name) || value.iter().any(|b| b.is_ascii_control() && *b != b'\t') {
+        return Err(RequestError::InvalidHeader(lossy()));
+    }
+    Ok((name, value))
+}
+
+fn trim_ows(mut value: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = value {
+        value = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = value {
+        value = rest;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_on;
+    use super::*;
+
+    async fn parse(raw: &[u8]) -> Result<RequestHead, RequestError> {
+        let mut reader = raw;
+        parse_head(read_head(&mut reader, &Limits::default()).await?)
+    }
+
+    async fn parse_err(raw: &[u8]) -> RequestError {
+        parse(raw).await.err().expect("head should be rejected")
+    }
+
+    #[test]
+    fn splits_request_line_and_fields() {
+        block_on(async {
+            let Ok(head) = parse(b"GET /a HTTP/1.1\r\nHost: example.com\r\nX-Empty:\r\nX-Padded: \t v \t\r\n\r\n").await else {
+                panic!("head should parse");
+            };
+            assert_eq!(&head.method[..], b"GET");
+            assert_eq!(&head.target[..], b"/a");
+            assert_eq!(&head.version[..], b"HTTP/1.1");
+            assert_eq!(head.headers.get("Host"), Some("example.com"));
+            assert_eq!(head.headers.get("X-Empty"), Some(""));
+            assert_eq!(head.headers.get("X-Padded"), Some("v"));
+        });
+    }
+
+    #[test]
+    fn rejects_whitespace_before_colon() {
+        block_on(async {
+            let err = parse_err(b"GET / HTTP/1.1\r\nHost : example.com\r\n\r\n").await;
+            assert!(matches!(err, RequestError::WhitespaceBeforeColon(_)), "{err:?}");
+            let err = parse_err(b"GET / HTTP/1.1\r\nHost\t: example.com\r\n\r\n").await;
+            assert!(matches!(err, RequestError::WhitespaceBeforeColon(_)), "{err:?}");
+        });
+    }
+
+    #[test]
+    fn rejects_obsolete_line_folding() {
+        block_on(async {
+            let err = parse_err(b"GET / HTTP/1.1\r\nX-Long: a\r\n b\r\n\r\n").await;
+            assert!(matches!(err, RequestError::ObsoleteLineFolding(_)), "{err:?}");
+            let err = parse_err(b"GET / HTTP/1.1\r\nX-Long: a\r\n\tb\r\n\r\n").await;
+            assert!(matches!(err, RequestError::ObsoleteLineFolding(_)), "{err:?}");
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_field_lines() {
+        block_on(async {
+            for raw in [
+                &b"GET / HTTP/1.1\r\nNo-Colon\r\n\r\n"[..],
+                b"GET / HTTP/1.1\r\n: no-name\r\n\r\n",
+                b"GET / HTTP/1.1\r\nX(Y): z\r\n\r\n",
+                b"GET / HTTP/1.1\r\nX-Cr: a\rb\r\n\r\n",
+            ] {
+                let err = parse_err(raw).await;
+                assert!(matches!(err, RequestError::InvalidHeader(_)), "{err:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn trailer_field_lines_follow_the_same_rules() {
+        assert_eq!(parse_field_line(b"X-Sum: 42 ").unwrap(), (&b"X-Sum"[..], &b"42"[..]));
+        assert!(matches!(parse_field_line(b"X-Sum : 42"), Err(RequestError::WhitespaceBeforeColon(_))));
+        assert!(matches!(parse_field_line(b" folded"), Err(RequestError::ObsoleteLineFolding(_))));
+        assert!(matches!(parse_field_line(b"X@Sum: 42"), Err(RequestError::InvalidHeader(_))));
+    }
+}
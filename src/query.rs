@@ -0,0 +1,141 @@
+use std::{collections::HashMap, ops::Deref, str::FromStr};
+
+use crate::uri::hex_value;
+
+/// Multi-map of `application/x-www-form-urlencoded` pairs,
+/// used both for query strings and for url-encoded request bodies.
+///
+/// Keys and values are percent-decoded and `+` is decoded as a space;
+/// repeated keys (`a=1&a=2`) keep every value in order.
+#[derive(Debug, Default, Clone)]
+pub struct Query(HashMap<String, Vec<String>>);
+
+impl Query {
+    pub fn new() -> Self {
+        Query(HashMap::new())
+    }
+
+    pub fn parse(input: &str) -> Self {
+        Self::parse_bytes(input.as_bytes())
+    }
+
+    pub fn parse_bytes(input: &[u8]) -> Self {
+        let mut query = Query::new();
+        for pair in input.split(|b| *b == b'&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.iter().position(|b| *b == b'=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, &[][..]),
+            };
+            query.append(form_decode(key), form_decode(value));
+        }
+        query
+    }
+
+    pub fn append(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.0.entry(key.into()).or_default().push(value.into());
+    }
+
+    /// First value for `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.first()).map(String::as_str)
+    }
+
+    /// Every value for `key`, in the order they were received
+    pub fn get_all(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.0.get(key).into_iter().flatten().map(String::as_str)
+    }
+
+    /// First value for `key` parsed as `T`, e.g. `query.get_as::<u32>("page")`
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.get(key).map(str::parse)
+    }
+
+    /// Every value for `key` parsed as `T`
+    pub fn get_all_as<T: FromStr>(&self, key: &str) -> Result<Vec<T>, T::Err> {
+        self.get_all(key).map(str::parse).collect()
+    }
+}
+
+/// Allow Query to behave like HashMap<String, Vec<String>>
+impl Deref for Query {
+    type Target = HashMap<String, Vec<String>>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromStr for Query {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Query::parse(s))
+    }
+}
+
+/// Lenient form decoding: `+` becomes a space and malformed `%` sequences are kept as-is.
+fn form_decode(input: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hi = input.get(i + 1).and_then(|b| hex_value(*b));
+                let lo = input.get(i + 2).and_then(|b| hex_value(*b));
+                if let (Some(hi), Some(lo)) = (hi, lo) {
+                    decoded.push(hi << 4 | lo);
+                    i += 3;
+                    continue;
+                }
+                decoded.push(b'%');
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    match String::from_utf8(decoded) {
+        Ok(s) => s,
+        Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pairs() {
+        let query = Query::parse("a=1&b=two&a=3&flag&empty=&&=novalue");
+        assert_eq!(query.get_all("a").collect::<Vec<_>>(), ["1", "3"]);
+        assert_eq!(query.get("b"), Some("two"));
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("empty"), Some(""));
+        assert_eq!(query.get(""), Some("novalue"));
+        assert_eq!(query.get("missing"), None);
+        assert_eq!(query.len(), 5);
+    }
+
+    #[test]
+    fn splits_before_decoding() {
+        let query = Query::parse("k%3Dx=v%26w&sp+ace=a+b%20c");
+        assert_eq!(query.get("k=x"), Some("v&w"));
+        assert_eq!(query.get("sp ace"), Some("a b c"));
+    }
+
+    #[test]
+    fn form_decoding_is_lenient() {
+        assert_eq!(form_decode(b"100%"), "100%");
+        assert_eq!(form_decode(b"%zz%4"), "%zz%4");
+        assert_eq!(form_decode(b"%2b+%2B"), "+ +");
+        assert_eq!(form_decode(b"%C3%A9"), "é");
+        assert_eq!(form_decode(b"%FFa"), "\u{FFFD}a");
+    }
+
+    #[test]
+    fn parses_typed_values() {
+        let query = Query::parse("page=2&ids=1&ids=x");
+        assert_eq!(query.get_as::<u32>("page"), Some(Ok(2)));
+        assert!(query.get_as::<u32>("missing").is_none());
+        assert!(query.get_all_as::<u32>("ids").is_err());
+        assert_eq!(Query::parse("ids=1&ids=2").get_all_as::<u32>("ids"), Ok(vec![1, 2]));
+    }
+}
@@ -0,0 +1,302 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::{SystemTime, UNIX_EPOCH}};
+use bytes::Bytes;
+use crate::{body::{Body, FileSegment}, headers::Headers, status_code::StatusCode, Method, Request, Response};
+
+/// More ranges than this in a single request are answered with the whole file
+const MAX_RANGES: usize = 16;
+
+/// Narrows a `200 OK` file response down to the byte ranges asked for by the request's `Range` header (RFC 9110 14).
+///
+/// A single range is sent as is with `Content-Range`, several as a `multipart/byteranges` body;
+/// ranges outside of the file are answered with `416 Range Not Satisfiable`.
+pub(crate) fn apply(req: &Request, res: &mut Response) {
+    if req.method != Method::GET || res.status.code != 200 {
+        return;
+    }
+    let Some(Body::File(file)) = &mut res.body else {
+        return;
+    };
+    let Some(range) = req.headers.get("Range") else {
+        return;
+    };
+    if let Some(if_range) = req.headers.get("If-Range") {
+        if !if_range_matches(if_range, &res.headers) {
+            return;
+        }
+    }
+    // unknown units and invalid syntax are ignored, the whole file is sent
+    let Some(ranges) = parse_range(range) else {
+        return;
+    };
+
+    let len = file.len;
+    let mut ranges: Vec<(u64, u64)> = ranges.into_iter().filter_map(|range| range.resolve(len)).collect();
+    if ranges.is_empty() {
+        res.status = StatusCode::RANGE_NOT_SATISFIABLE;
+        res.headers.insert(("Content-Range", format!("bytes */{len}")));
+        res.headers.insert(("Content-Length", "0"));
+        res.headers.remove("Transfer-Encoding");
+        res.body = Some(Body::Bytes(Bytes::new()));
+        return;
+    }
+    ranges = coalesce(ranges);
+    if ranges.len() > MAX_RANGES {
+        return;
+    }
+
+    res.status = StatusCode::PARTIAL_CONTENT;
+    res.headers.remove("Transfer-Encoding");
+    if let [(start, end)] = ranges[..] {
+        res.headers.insert(("Content-Range", format!("bytes {start}-{end}/{len}")));
+        res.headers.insert(("Content-Length", (end - start + 1).to_string()));
+        file.segments = Some(vec![FileSegment { head: Bytes::new(), start, len: end - start + 1 }]);
+        return;
+    }
+
+    let boundary = boundary();
+    let content_type = res.headers.get("Content-Type").map(|content_type| format!("Content-Type: {content_type}\r\n")).unwrap_or_default();
+    let segments: Vec<FileSegment> = ranges
+        .iter()
+        .map(|&(start, end)| FileSegment {
+            head: Bytes::from(format!("\r\n--{boundary}\r\n{content_type}Content-Range: bytes {start}-{end}/{len}\r\n\r\n")),
+            start,
+            len: end - start + 1,
+        })
+        .collect();
+    file.tail = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+    let content_len = segments.iter().map(|segment| segment.head.len() as u64 + segment.len).sum::<u64>() + file.tail.len() as u64;
+    file.segments = Some(segments);
+
+    res.headers.insert(("Content-Type", format!("multipart/byteranges; boundary={boundary}")));
+    res.headers.insert(("Content-Length", content_len.to_string()));
+}
+
+/// `If-Range` holds either an entity tag, compared strongly with `ETag`, or a date equal to `Last-Modified` (RFC 9110 13.1.5)
+fn if_range_matches(if_range: &str, headers: &Headers) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !if_range.starts_with("W/") && headers.get("ETag").is_some_and(|etag| etag == if_range);
+    }
+    let last_modified = headers.get("Last-Modified").and_then(|date| httpdate::parse_http_date(date).ok());
+    last_modified.is_some_and(|last_modified| httpdate::parse_http_date(if_range).is_ok_and(|date| date == last_modified))
+}
+
+enum ByteRange {
+    /// `first-` or `first-last`
+    FromTo(u64, Option<u64>),
+    /// `-suffix`, the last `suffix` bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Inclusive bounds within a file of `len` bytes, `None` if unsatisfiable
+    fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(first, last) if first < len => Some((first, last.unwrap_or(u64::MAX).min(len - 1))),
+            ByteRange::Suffix(suffix) if suffix > 0 && len > 0 => Some((len.saturating_sub(suffix), len - 1)),
+            _ => None,
+        }
+    }
+}
+
+/// `bytes=0-499, 1000-, -500`
+fn parse_range(range: &str) -> Option<Vec<ByteRange>> {
+    let (unit, ranges) = range.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let parse = |n: &str| n.bytes().all(|b| b.is_ascii_digit()).then(|| n.parse::<u64>().ok()).flatten();
+    ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let (first, last) = range.split_once('-')?;
+            match (first, last) {
+                ("", suffix) => Some(ByteRange::Suffix(parse(suffix)?)),
+                (first, "") => Some(ByteRange::FromTo(parse(first)?, None)),
+                (first, last) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    (first <= last).then_some(ByteRange::FromTo(first, Some(last)))
+                }
+            }
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|ranges| !ranges.is_empty())
+}
+
+/// Merges overlapping and adjacent ranges so that no byte is sent twice, otherwise keeps the client's order
+fn coalesce(ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut sorted = ranges.clone();
+    sorted.sort_unstable();
+    if sorted.windows(2).all(|w| w[1].0 > w[0].1.saturating_add(1)) {
+        return ranges;
+    }
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+    for (start, end) in sorted {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Unique per response, the parts are file bytes that could contain any fixed string
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    format!("{nanos:016x}{:08x}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::{block_on, extensions::Extensions, uri::Uri, version::Version};
+    use super::*;
+
+    fn get(headers: &[(&str, &str)]) -> Request {
+        let mut req = Request {
+            method: Method::GET,
+            path: "/file".to_owned(),
+            uri: Uri::parse("/file").unwrap(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            extensions: Extensions::new(),
+            body: None,
+        };
+        for &header in headers {
+            req.headers.insert(header);
+        }
+        req
+    }
+
+    /// A file holding `content`, unique to `name`
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("http-tokio-range-{}-{name}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn alphabet_file(name: &str) -> PathBuf {
+        temp_file(name, b"abcdefghijklmnopqrstuvwxyz")
+    }
+
+    /// `range` resolved within a file of `len` bytes, `None` if it isn't a valid `Range` value
+    fn resolve(range: &str, len: u64) -> Option<Vec<Option<(u64, u64)>>> {
+        parse_range(range).map(|ranges| ranges.iter().map(|range| range.resolve(len)).collect())
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(resolve("bytes=0-9", 26), Some(vec![Some((0, 9))]));
+        assert_eq!(resolve("Bytes = 10- , -5,,", 26), Some(vec![Some((10, 25)), Some((21, 25))]));
+        assert_eq!(resolve("bytes=20-99", 26), Some(vec![Some((20, 25))]));
+        assert_eq!(resolve("bytes=-99", 26), Some(vec![Some((0, 25))]));
+        assert_eq!(resolve("bytes=26-, -0", 26), Some(vec![None, None]));
+        assert_eq!(resolve("bytes=0-", 0), Some(vec![None]));
+        for range in ["bytes=", "bytes=,", "items=0-9", "bytes=9-0", "bytes=a-b", "bytes=+1-2", "bytes=0-9x", "bytes=1", "0-9"] {
+            assert_eq!(resolve(range, 26), None, "{range}");
+        }
+    }
+
+    #[test]
+    fn coalesces_overlapping_and_adjacent_ranges() {
+        assert_eq!(coalesce(vec![(20, 25), (0, 4)]), [(20, 25), (0, 4)]);
+        assert_eq!(coalesce(vec![(10, 19), (0, 4), (5, 9)]), [(0, 19)]);
+        assert_eq!(coalesce(vec![(0, 9), (3, 5), (20, 25), (8, 12)]), [(0, 12), (20, 25)]);
+        assert_eq!(coalesce(vec![(0, u64::MAX), (5, 6)]), [(0, u64::MAX)]);
+    }
+
+    #[test]
+    fn sends_single_and_multiple_ranges() {
+        let path = alphabet_file("ranges");
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        apply(&get(&[("Range", "bytes=-3")]), &mut res);
+        assert_eq!(res.status.code, 206);
+        assert_eq!(res.headers.get("Content-Range"), Some("bytes 23-25/26"));
+        assert_eq!(res.headers.get("Content-Length"), Some("3"));
+
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        apply(&get(&[("Range", "bytes=0-1, 10-11, 1-2")]), &mut res);
+        assert_eq!(res.status.code, 206);
+        assert!(res.headers.get("Content-Type").unwrap().starts_with("multipart/byteranges; boundary="));
+        let Some(Body::File(file)) = &res.body else { panic!("file body expected") };
+        let segments: Vec<(u64, u64)> = file.segments.as_ref().unwrap().iter().map(|segment| (segment.start, segment.len)).collect();
+        assert_eq!(segments, [(0, 3), (10, 2)]);
+        assert!(String::from_utf8_lossy(&file.segments.as_ref().unwrap()[1].head).contains("Content-Range: bytes 10-11/26\r\n"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn answers_unsatisfiable_ranges_with_416() {
+        let path = alphabet_file("unsatisfiable");
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        apply(&get(&[("Range", "bytes=26-, 30-40")]), &mut res);
+        assert_eq!(res.status.code, 416);
+        assert_eq!(res.headers.get("Content-Range"), Some("bytes */26"));
+        assert_eq!(res.headers.get("Content-Length"), Some("0"));
+        assert!(matches!(res.body, Some(Body::Bytes(ref bytes)) if bytes.is_empty()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sends_the_whole_file_otherwise() {
+        let path = alphabet_file("whole");
+        for headers in [&[("Range", "bytes=5-1")][..], &[("Range", "lines=1-2")], &[]] {
+            let mut res = block_on(Response::build().file(&path)).unwrap();
+            apply(&get(headers), &mut res);
+            assert_eq!(res.status.code, 200, "{headers:?}");
+            assert_eq!(res.headers.get("Content-Length"), Some("26"));
+        }
+        std::fs::remove_file(path).unwrap();
+
+        let path = temp_file("many", &[b'a'; 64]);
+        let too_many = format!("bytes={}", (0..=MAX_RANGES).map(|i| format!("{}-{}", i * 2, i * 2)).collect::<Vec<_>>().join(","));
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        apply(&get(&[("Range", &too_many)]), &mut res);
+        assert_eq!(res.status.code, 200);
+
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        let mut head = get(&[("Range", "bytes=0-1")]);
+        head.method = Method::HEAD;
+        apply(&head, &mut res);
+        assert_eq!(res.status.code, 200);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resumes_with_the_last_modified_date() {
+        let path = alphabet_file("date");
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        let last_modified = res.headers.get("Last-Modified").unwrap().to_owned();
+        apply(&get(&[("Range", "bytes=10-"), ("If-Range", &last_modified)]), &mut res);
+        assert_eq!(res.status.code, 206);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resumes_with_the_file_etag() {
+        let path = alphabet_file("etag");
+        let mut res = block_on(Response::build().file(&path)).unwrap();
+        let etag = res.headers.get("ETag").unwrap().to_owned();
+        assert!(etag.starts_with('"'), "{etag}");
+
+        apply(&get(&[("Range", "bytes=10-"), ("If-Range", &etag)]), &mut res);
+        assert_eq!(res.status.code, 206);
+        assert_eq!(res.headers.get("Content-Range"), Some("bytes 10-25/26"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sends_the_whole_file_when_if_range_does_not_match() {
+        let path = alphabet_file("stale");
+        for if_range in ["\"other\"", "W/\"other\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let mut res = block_on(Response::build().file(&path)).unwrap();
+            apply(&get(&[("Range", "bytes=10-"), ("If-Range", if_range)]), &mut res);
+            assert_eq!(res.status.code, 200, "{if_range}");
+            assert_eq!(res.headers.get("Content-Range"), None);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
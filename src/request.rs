@@ -1,11 +1,17 @@
-use crate::TcpIO;
+use crate::{limits::Limits, method::{InvalidMethod, Method}, query::Query, status_code::StatusCode, upgrade::OnUpgrade, uri::{Uri, UriError, UriForm}, version::Version, TcpIO};
 
-use super::{extensions::Extensions, headers::Headers};
+#[cfg(feature = "http2")]
+use crate::headers::HeaderName;
+
+use super::{extensions::Extensions, headers::{HeaderValue, Headers}, parser::{parse_head, read_head, RequestHead}};
 
 #[derive(Debug)]
 pub struct Request<T> {
-    pub method: String,
+    pub method: Method,
+    /// Percent-decoded path without trailing slashes, e.g. `/users/42`
     pub path: String,
+    pub uri: Uri,
+    pub version: Version,
     pub headers: Headers,
     pub extensions: Extensions,
     pub body: Option<T>,
@@ -17,64 +23,94 @@ impl IncomingRequest {
     pub async fn content_len(&self) -> Option<usize> {
         self.extensions.get::<ContentLength>().await.map(|cl| cl.0)
     }
+
+    /// Whether the client wants the connection kept open after this request:
+    /// HTTP/1.1 defaults to persistent connections, HTTP/1.0 only opts in with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 | Version::Http2 => !self.headers.contains_token("Connection", "close"),
+            Version::Http10 => self.headers.contains_token("Connection", "keep-alive"),
+        }
+    }
+
+    /// Whether the client sent `Expect: 100-continue` and waits for `100 Continue` before sending the body
+    pub fn expects_continue(&self) -> bool {
+        self.version == Version::Http11 && self.headers.contains_token("Expect", "100-continue")
+    }
+
+    /// Decoded query string, empty when the target has none
+    pub fn query(&self) -> Query {
+        self.uri.query().map(Query::parse).unwrap_or_default()
+    }
+
+    /// Future resolving to the connection's IO once the response switched protocols,
+    /// `None` if the request has no `Upgrade` header and isn't a `CONNECT`, or if it was already taken.
+    ///
+    /// The connection is handed over after a `101 Switching Protocols` response, or a `2xx` response to `CONNECT`, has been sent.
+    pub async fn on_upgrade(&self) -> Option<OnUpgrade> {
+        self.extensions.lock().await.remove::<OnUpgrade>()
+    }
+
+    /// ID of the last Server-Sent Event received by a reconnecting client, events after it should be sent again
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.headers.get("Last-Event-ID")
+    }
 }
 
 struct ContentLength(usize);
 
 impl TcpIO {
     pub async fn receive_request(&mut self) -> Result<IncomingRequest, RequestError> {
-        let (first_line_len, first_line) = self.read_line().await?;
-        if first_line_len == 0 { return Err(RequestError::ConnectionClosed) }
-        let mut parts = first_line.split_whitespace();
-        let method = parts
-            .next()
-            .ok_or_else(|| RequestError::InvalidRequestLine(first_line.clone()))?
-            .to_uppercase()
-            .to_string();
-        let full_path = parts
-            .next()
-            .ok_or_else(|| RequestError::InvalidRequestLine(first_line.clone()))?
-            .to_string();
-
-        // TODO: URI Struct
-        let mut full_path = full_path.split("?");
-        let path = "/".to_owned() + full_path.next().unwrap_or("/").trim_matches('/');
-        let _query_string = full_path.next().unwrap_or("");
-
-        let http_version = parts
-            .next()
-            .ok_or_else(|| RequestError::InvalidRequestLine(first_line.clone()))?
-            .to_string();
-
-        if !http_version.eq("HTTP/1.1") {
-            return Err(RequestError::UnsupportedHttpVersion(http_version));
+        self.receive_request_with(&Limits::default()).await
+    }
+
+    pub async fn receive_request_with(&mut self, limits: &Limits) -> Result<IncomingRequest, RequestError> {
+        let head = read_head(self.reader(), limits).await?;
+        let RequestHead { method, target, version, headers } = parse_head(head)?;
+        let request_line = || {
+            let line = [&method[..], &target[..], &version[..]].join(&b' ');
+            RequestError::InvalidRequestLine(String::from_utf8_lossy(&line).into_owned())
+        };
+
+        let method = Method::from_bytes(&method)?;
+
+        let uri = Uri::parse(&String::from_utf8_lossy(&target))?;
+        // authority-form is reserved to CONNECT and asterisk-form to OPTIONS (RFC 9112 3.2.3, 3.2.4)
+        let form_allowed = match uri.form() {
+            UriForm::Origin | UriForm::Absolute => method != Method::CONNECT,
+            UriForm::Authority => method == Method::CONNECT,
+            UriForm::Asterisk => method == Method::OPTIONS,
+        };
+        if !form_allowed {
+            return Err(request_line());
         }
+        let path = request_path(&uri);
+
+        let version = Version::parse(&version)
+            .ok_or_else(|| RequestError::UnsupportedHttpVersion(String::from_utf8_lossy(&version).into_owned()))?;
 
-        // parsing headers
-        let mut headers = Headers::new();
         let extensions = Extensions::new();
-        loop {
-            let (len, line) = self.read_line().await?;
-            if len <= 2 {
-                break; // Empty line signals end of headers
+
+        // message framing (RFC 9112 6.3)
+        let content_length = parse_content_length(&headers)?;
+        if headers.get_value("Transfer-Encoding").is_some() {
+            if content_length.is_some() {
+                return Err(RequestError::ContentLengthWithTransferEncoding);
             }
-            if let Some((key, value)) = line.split_once(":") {
-                let key = key.trim();
-                let value = value.trim();
-                if key.eq_ignore_ascii_case("content-length") {
-                    match value.parse::<usize>() {
-                        Ok(length) => {
-                            headers.append((key, value));
-                            extensions.insert(ContentLength(length)).await;
-                        }
-                        Err(_) => {
-                            return Err(RequestError::InvalidContentLength(value.to_string()));
-                        }
-                    }
-                }
-                headers.append((key, value));
-            } else {
-                return Err(RequestError::InvalidHeader(line));
+            // HTTP/1.0 has no transfer codings, the framing can't be trusted (RFC 9112 6.1)
+            if version == Version::Http10 {
+                return Err(RequestError::TransferEncodingInHttp10);
+            }
+            check_transfer_encoding(&headers)?;
+        }
+        if let Some(length) = content_length {
+            extensions.insert(ContentLength(length)).await;
+        }
+
+        // 100-continue is the only expectation defined, HTTP/1.0 expectations are ignored (RFC 9110 10.1.1)
+        if let Some(expect) = headers.get_value("Expect") {
+            if version != Version::Http10 && !expect.to_str().is_some_and(|e| e.trim().eq_ignore_ascii_case("100-continue")) {
+                return Err(RequestError::UnsupportedExpectation(expect.to_string()));
             }
         }
 
@@ -82,12 +118,100 @@ impl TcpIO {
             headers,
             method,
             path,
+            uri,
+            version,
             extensions,
             body: None,
         })
     }
 }
 
+#[cfg(feature = "http2")]
+impl IncomingRequest {
+    /// Request received on an HTTP/2 stream: the pseudo-header fields give the target, `:authority` stands in for `Host`
+    pub(crate) async fn from_http2(parts: &http::request::Parts) -> Result<Self, RequestError> {
+        let method = Method::from_bytes(parts.method.as_str().as_bytes())?;
+        let target = if method == Method::CONNECT {
+            parts.uri.authority().map(|authority| authority.as_str())
+        } else {
+            parts.uri.path_and_query().map(|path| path.as_str())
+        };
+        let uri = Uri::parse(target.unwrap_or("/"))?;
+
+        let mut headers = Headers::new();
+        for (name, value) in &parts.headers {
+            headers.append_value(HeaderName::from(name.as_str()), HeaderValue::from_bytes(bytes::Bytes::copy_from_slice(value.as_bytes())));
+        }
+        if let Some(authority) = parts.uri.authority().filter(|_| !headers.contains_key("Host")) {
+            headers.insert(("Host", authority.as_str()));
+        }
+
+        let extensions = Extensions::new();
+        if let Some(length) = parse_content_length(&headers)? {
+            extensions.insert(ContentLength(length)).await;
+        }
+
+        Ok(IncomingRequest {
+            path: request_path(&uri),
+            headers,
+            method,
+            uri,
+            version: Version::Http2,
+            extensions,
+            body: None,
+        })
+    }
+}
+
+/// Path without trailing slashes, `*` for an asterisk-form target
+fn request_path(uri: &Uri) -> String {
+    match uri.form() {
+        UriForm::Asterisk => "*".to_string(),
+        _ => "/".to_owned() + uri.path().trim_matches('/'),
+    }
+}
+
+/// Every `Content-Length` field line and list element must carry the same value
+fn parse_content_length(headers: &Headers) -> Result<Option<usize>, RequestError> {
+    let Some(values) = headers.get_all("Content-Length") else {
+        return Ok(None);
+    };
+    let mut length = None;
+    let values = values.iter().map(|v| v.to_str().ok_or_else(|| RequestError::InvalidContentLength(v.to_string()))).collect::<Result<Vec<_>, _>>()?;
+    for value in values.iter().flat_map(|v| v.split(',')).map(str::trim) {
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(RequestError::InvalidContentLength(value.to_string()));
+        }
+        let parsed = value.parse::<usize>().map_err(|_| RequestError::InvalidContentLength(value.to_string()))?;
+        match length {
+            Some(length) if length != parsed => return Err(RequestError::ConflictingContentLength(values.join(", "))),
+            _ => length = Some(parsed),
+        }
+    }
+    Ok(length)
+}
+
+/// The body reader only decodes `chunked`, so it must be the one and only transfer coding (RFC 9112 6.1)
+fn check_transfer_encoding(headers: &Headers) -> Result<(), RequestError> {
+    let values = headers.get_all("Transfer-Encoding").map(Vec::as_slice).unwrap_or_default();
+    let joined = || values.iter().map(HeaderValue::to_string).collect::<Vec<_>>().join(", ");
+    let mut chunked = 0;
+    for value in values {
+        let value = value.to_str().ok_or_else(|| RequestError::InvalidTransferEncoding(joined()))?;
+        // empty list elements don't count (RFC 9110 5.6.1)
+        for coding in value.split(',').map(str::trim).filter(|coding| !coding.is_empty()) {
+            if !coding.eq_ignore_ascii_case("chunked") {
+                return Err(RequestError::UnsupportedTransferEncoding(joined()));
+            }
+            chunked += 1;
+        }
+    }
+    match chunked {
+        1 => Ok(()),
+        _ => Err(RequestError::InvalidTransferEncoding(joined())),
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum RequestError {
     #[error("could not read from TcpStream: {0}")]
@@ -99,6 +223,30 @@ pub enum RequestError {
     #[error("invalid request line: \"{0}\"")]
     InvalidRequestLine(String),
 
+    #[error("request line too long")]
+    RequestLineTooLong,
+
+    #[error("header line too long")]
+    HeaderTooLarge,
+
+    #[error("too many headers")]
+    TooManyHeaders,
+
+    #[error("request head too large")]
+    HeadTooLarge,
+
+    #[error("request body exceeds the limit of {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("unsupported expectation: {0:?}")]
+    UnsupportedExpectation(String),
+
+    #[error(transparent)]
+    InvalidMethod(#[from] InvalidMethod),
+
+    #[error("invalid request target: {0}")]
+    InvalidUri(#[from] UriError),
+
     #[error("unsupported http version: \"{0}\"")]
     UnsupportedHttpVersion(String),
 
@@ -107,9 +255,243 @@ pub enum RequestError {
 
     #[error("invalid content length header: {0:?}")]
     InvalidContentLength(String),
+
+    #[error("conflicting content length headers: {0:?}")]
+    ConflictingContentLength(String),
+
+    #[error("both content length and transfer encoding headers are present")]
+    ContentLengthWithTransferEncoding,
+
+    #[error("transfer encoding on an HTTP/1.0 request")]
+    TransferEncodingInHttp10,
+
+    #[error("invalid transfer encoding, chunked must be applied exactly once: {0:?}")]
+    InvalidTransferEncoding(String),
+
+    #[error("unsupported transfer encoding, only chunked is implemented: {0:?}")]
+    UnsupportedTransferEncoding(String),
+
+    #[error("whitespace between header name and colon: {0:?}")]
+    WhitespaceBeforeColon(String),
+
+    #[error("obsolete line folding in header: {0:?}")]
+    ObsoleteLineFolding(String),
+
+    #[error("unsupported media type: {0:?}")]
+    UnsupportedMediaType(String),
+
+    #[error("unsupported content encoding: {0:?}")]
+    UnsupportedContentEncoding(String),
     // #[error("body has already been consumed")]
     // BodyAlreadyConsumed,
 
-    // #[error("invalid json body")]
-    // Json(#[from] serde_json::Error),
+    #[cfg(feature = "json")]
+    #[error("invalid json body: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl RequestError {
+    /// Status code of the error response suggested for this error
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            RequestError::InvalidRequestLine(_) => StatusCode::BAD_REQUEST,
+            RequestError::InvalidHeader(_) => StatusCode::BAD_REQUEST,
+            RequestError::InvalidContentLength(_) => StatusCode::BAD_REQUEST,
+            RequestError::ConflictingContentLength(_) => StatusCode::BAD_REQUEST,
+            RequestError::ContentLengthWithTransferEncoding => StatusCode::BAD_REQUEST,
+            RequestError::TransferEncodingInHttp10 => StatusCode::BAD_REQUEST,
+            RequestError::InvalidTransferEncoding(_) => StatusCode::BAD_REQUEST,
+            RequestError::WhitespaceBeforeColon(_) => StatusCode::BAD_REQUEST,
+            RequestError::ObsoleteLineFolding(_) => StatusCode::BAD_REQUEST,
+            RequestError::InvalidUri(_) => StatusCode::BAD_REQUEST,
+            RequestError::InvalidMethod(_) => StatusCode::BAD_REQUEST,
+            #[cfg(feature = "json")]
+            RequestError::Json(_) => StatusCode::BAD_REQUEST,
+            RequestError::RequestLineTooLong => StatusCode::URI_TOO_LONG,
+            RequestError::HeaderTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            RequestError::TooManyHeaders => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            RequestError::HeadTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            RequestError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            RequestError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RequestError::UnsupportedContentEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RequestError::UnsupportedTransferEncoding(_) => StatusCode::NOT_IMPLEMENTED,
+            RequestError::UnsupportedHttpVersion(_) => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            RequestError::UnsupportedExpectation(_) => StatusCode::EXPECTATION_FAILED,
+            RequestError::Read(_) | RequestError::ConnectionClosed => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}};
+    use crate::{block_on, BodyReader};
+    use super::*;
+
+    /// Connection whose client already sent `raw`, the client end is kept open so reads don't hit EOF
+    async fn connection(raw: &[u8]) -> (TcpIO, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        client.write_all(raw).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (TcpIO::new(server), client)
+    }
+
+    async fn receive(raw: &[u8]) -> Result<IncomingRequest, RequestError> {
+        let (mut io, _client) = connection(raw).await;
+        io.receive_request_with(&Limits::default()).await
+    }
+
+    /// Reads the chunked body following the head in `raw`
+    async fn read_chunked(raw: &[u8]) -> io::Result<Vec<u8>> {
+        let (mut io, _client) = connection(raw).await;
+        io.receive_request_with(&Limits::default()).await.unwrap();
+        BodyReader::chunked(io).read_all().await
+    }
+
+    #[test]
+    fn accepts_valid_framing() {
+        block_on(async {
+            let req = receive(b"POST /a HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 5\r\nContent-Length: 5\r\n\r\n").await.unwrap();
+            assert_eq!(req.content_len().await, Some(5));
+            receive(b"POST /a HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: Chunked\r\n\r\n").await.unwrap();
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_content_length() {
+        block_on(async {
+            for value in ["", "-1", "+5", "0x10", "5 5", "99999999999999999999999"] {
+                let raw = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {value}\r\n\r\n");
+                let err = receive(raw.as_bytes()).await.unwrap_err();
+                assert!(matches!(err, RequestError::InvalidContentLength(_)), "{value:?}: {err:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn rejects_conflicting_content_length() {
+        block_on(async {
+            let err = receive(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n").await.unwrap_err();
+            assert!(matches!(err, RequestError::ConflictingContentLength(_)), "{err:?}");
+            let err = receive(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 6\r\n\r\n").await.unwrap_err();
+            assert!(matches!(err, RequestError::ConflictingContentLength(_)), "{err:?}");
+            assert_eq!(err.status_code().code, 400);
+        });
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        block_on(async {
+            let err = receive(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n").await.unwrap_err();
+            assert!(matches!(err, RequestError::ContentLengthWithTransferEncoding), "{err:?}");
+            assert_eq!(err.status_code().code, 400);
+        });
+    }
+
+    #[test]
+    fn rejects_transfer_encoding_in_http10() {
+        block_on(async {
+            let err = receive(b"POST / HTTP/1.0\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n").await.unwrap_err();
+            assert!(matches!(err, RequestError::TransferEncodingInHttp10), "{err:?}");
+            assert_eq!(err.status_code().code, 400);
+        });
+    }
+
+    #[test]
+    fn rejects_unsupported_transfer_codings() {
+        block_on(async {
+            for value in ["gzip, chunked", "chunked, gzip", "identity", "chunked\r\nTransfer-Encoding: gzip"] {
+                let raw = format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {value}\r\n\r\n");
+                let err = receive(raw.as_bytes()).await.unwrap_err();
+                assert!(matches!(err, RequestError::UnsupportedTransferEncoding(_)), "{value:?}: {err:?}");
+                assert_eq!(err.status_code().code, 501);
+            }
+            for value in ["chunked, chunked", ","] {
+                let raw = format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {value}\r\n\r\n");
+                let err = receive(raw.as_bytes()).await.unwrap_err();
+                assert!(matches!(err, RequestError::InvalidTransferEncoding(_)), "{value:?}: {err:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn rejects_malformed_header_fields() {
+        block_on(async {
+            let err = receive(b"GET / HTTP/1.1\r\nHost : a\r\n\r\n").await.unwrap_err();
+            assert!(matches!(err, RequestError::WhitespaceBeforeColon(_)), "{err:?}");
+            let err = receive(b"GET / HTTP/1.1\r\nHost: a\r\nX-Long: b\r\n c\r\n\r\n").await.unwrap_err();
+            assert!(matches!(err, RequestError::ObsoleteLineFolding(_)), "{err:?}");
+        });
+    }
+
+    const CHUNKED_HEAD: &str = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+    #[test]
+    fn reads_chunked_body() {
+        block_on(async {
+            let body = read_chunked(format!("{CHUNKED_HEAD}5;name=value ; q=\"a\\\"b\"\r\nhello\r\nA\r\n 01234567\n\r\n0\r\n\r\n").as_bytes()).await.unwrap();
+            assert_eq!(body, b"hello 01234567\n");
+        });
+    }
+
+    #[test]
+    fn rejects_bad_chunk_sizes() {
+        block_on(async {
+            for chunks in [
+                " 5\r\nhello\r\n0\r\n\r\n",
+                "5 \r\nhello\r\n0\r\n\r\n",
+                "-5\r\nhello\r\n0\r\n\r\n",
+                "0x5\r\nhello\r\n0\r\n\r\n",
+                "\r\nhello\r\n0\r\n\r\n",
+                "fffffffffffffffff\r\nhello\r\n0\r\n\r\n",
+            ] {
+                let err = read_chunked(format!("{CHUNKED_HEAD}{chunks}").as_bytes()).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{chunks:?}: {err}");
+            }
+        });
+    }
+
+    #[test]
+    fn rejects_bad_chunk_extensions() {
+        block_on(async {
+            for chunks in ["5;\r\nhello\r\n0\r\n\r\n", "5;a\rb\r\nhello\r\n0\r\n\r\n", "5;a=\r\nhello\r\n0\r\n\r\n", "5;a=\"b\r\nhello\r\n0\r\n\r\n", "5 x\r\nhello\r\n0\r\n\r\n"] {
+                let err = read_chunked(format!("{CHUNKED_HEAD}{chunks}").as_bytes()).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{chunks:?}: {err}");
+            }
+        });
+    }
+
+    #[test]
+    fn rejects_bare_lf_in_chunked_body() {
+        block_on(async {
+            for chunks in ["5\nhello\r\n0\r\n\r\n", "5\r\nhello\n0\r\n\r\n", "5\r\nhello\r\n0\n\r\n", "5\r\nhello\r\n0\r\n\n"] {
+                let err = read_chunked(format!("{CHUNKED_HEAD}{chunks}").as_bytes()).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{chunks:?}: {err}");
+            }
+        });
+    }
+
+    #[test]
+    fn rejects_malformed_trailers() {
+        block_on(async {
+            for trailer in ["X-Sum : 1", " folded", "X(Sum): 1", "no colon"] {
+                let err = read_chunked(format!("{CHUNKED_HEAD}5\r\nhello\r\n0\r\n{trailer}\r\n\r\n").as_bytes()).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{trailer:?}: {err}");
+            }
+        });
+    }
+
+    #[test]
+    fn framing_errors_are_final() {
+        block_on(async {
+            // the lines after an invalid chunk size would otherwise parse as the end of the body
+            let (mut io, _client) = connection(format!("{CHUNKED_HEAD}fffffffffffffffff\r\n0\r\n\r\n").as_bytes()).await;
+            io.receive_request_with(&Limits::default()).await.unwrap();
+            let body = BodyReader::chunked(io);
+            assert!(body.read_all().await.is_err());
+            assert!(body.drain().await.is_err());
+        });
+    }
 }
@@ -1,33 +1,50 @@
-use std::path::Path;
+use std::{future::Future, path::Path, pin::Pin, time::Duration};
 use bytes::Bytes;
 use httpdate::HttpDate;
 use thiserror::Error;
-use tokio::{fs::File, io::AsyncWriteExt};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt}};
 use tokio_stream::{Stream, StreamExt};
-use tokio_util::io::ReaderStream;
-use crate::{body::Body, content_type::ContentType};
+use crate::{body::{Body, FileBody, FileSegment}, content_type::ContentType, sse::{Event, EventStream, DEFAULT_KEEP_ALIVE}, version::Version};
 use super::{extensions::Extensions, headers::Headers, status_code::StatusCode, TcpIO};
 
 #[derive(Debug)]
 pub struct Response<T> {
+    pub version: Version,
     pub status: StatusCode,
     pub headers: Headers,
     pub extensions: Extensions,
     pub body: Option<T>,
+    /// Takes over the connection once a `101 Switching Protocols` response has been sent
+    pub(crate) upgrade: Option<UpgradeHandler>,
+}
+
+type UpgradeFn = Box<dyn FnOnce(TcpIO) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+pub(crate) struct UpgradeHandler(pub(crate) UpgradeFn);
+
+impl std::fmt::Debug for UpgradeHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("UpgradeHandler")
+    }
 }
 
 impl<T> Response<T> {
     fn new() -> Self {
         Response {
             body: None,
+            version: Version::Http11,
             status: StatusCode::OK,
             headers: Headers::new(),
             extensions: Extensions::new(),
+            upgrade: None,
         }
     }
 
-    fn fmt_head(&self) -> String {
-        format!("HTTP/1.1 {} \r\n{}\r\n\r\n",self.status, self.headers.to_string())
+    fn fmt_head(&self) -> Vec<u8> {
+        let mut head = format!("{} {} \r\n", self.version, self.status).into_bytes();
+        self.headers.write_to(&mut head);
+        head.extend_from_slice(b"\r\n");
+        head
     }
 }
 
@@ -43,7 +60,24 @@ impl HttpResponse {
     }
 
     pub async fn send(&mut self, io: &mut TcpIO) -> Result<(), ResponseError> {
-        let mut payload = self.fmt_head().into_bytes();
+        // 1xx, 204 and 304 responses never have content (RFC 9110 6.4.1)
+        let code = self.status.code;
+        if (100..200).contains(&code) || code == 204 || code == 304 {
+            self.body = None;
+            if code != 304 {
+                self.headers.remove("Content-Length");
+                self.headers.remove("Transfer-Encoding");
+            }
+        }
+
+        // HTTP/1.0 clients don't know chunked encoding, the stream is delimited by closing the connection
+        let chunked = self.version != Version::Http10;
+        if !chunked && self.headers.is_chunked() {
+            self.headers.remove("Transfer-Encoding");
+            self.headers.insert(("Connection", "close"));
+            self.headers.remove("Keep-Alive");
+        }
+        let mut payload = self.fmt_head();
 
         if let Some(body) = self.body.take() {
             match body {
@@ -57,13 +91,39 @@ impl HttpResponse {
                     
                     while let Some(chunk) = stream.next().await {
                         let chunk = chunk?;
+                        if !chunked {
+                            io.writer().write_all(&chunk).await?;
+                            continue;
+                        }
                         let chunk_len = format!("{:X}\r\n", chunk.len());
                         io.writer().write_all(chunk_len.as_bytes()).await?;
                         io.writer().write_all(&chunk).await?;
                         io.writer().write_all(b"\r\n").await?;
                     }
                     
-                    io.writer().write_all(b"0\r\n\r\n").await?; // End of stream
+                    if chunked {
+                        io.writer().write_all(b"0\r\n\r\n").await?; // End of stream
+                    }
+                    io.writer().flush().await?;
+                },
+                Body::File(FileBody { mut file, len, segments, tail }) => {
+                    io.writer().write_all(&payload).await?;
+                    let segments = segments.unwrap_or_else(|| vec![FileSegment { head: Bytes::new(), start: 0, len }]);
+                    for segment in segments {
+                        io.writer().write_all(&segment.head).await?;
+                        let copied = match io.send_file(&file, segment.start, segment.len).await? {
+                            Some(sent) => sent,
+                            None => {
+                                file.seek(std::io::SeekFrom::Start(segment.start)).await?;
+                                tokio::io::copy(&mut (&mut file).take(segment.len), io.writer()).await?
+                            },
+                        };
+                        // the file shrank since the response was built, Content-Length can't be honored
+                        if copied < segment.len {
+                            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                        }
+                    }
+                    io.writer().write_all(&tail).await?;
                     io.writer().flush().await?;
                 },
             }
@@ -98,6 +158,18 @@ impl ResponseBuilder {
         self.inner
     }
 
+    /// Serializes `value` as the JSON body, with an `application/json` content type unless one is already set.
+    ///
+    /// A serialization error is a server error, it should be answered with `500 Internal Server Error`.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(mut self, value: &T) -> Result<HttpResponse, ResponseError> {
+        let body = serde_json::to_vec(value)?;
+        if !self.inner.headers.contains_key("Content-Type") {
+            self.inner.headers.content_type(ContentType::Json);
+        }
+        Ok(self.body(body))
+    }
+
     pub fn stream<S: Stream<Item = Result<Bytes, ResponseError>> + Send + Sync + Unpin + 'static>(mut self, body: S) -> HttpResponse {
         if !self.inner.headers.contains_key("Content-Type") {
             self.inner.headers.content_type(ContentType::OctetStream);
@@ -108,14 +180,78 @@ impl ResponseBuilder {
         self.inner
     }
 
+    /// `101 Switching Protocols` response switching the connection to `protocol`,
+    /// `on_upgrade` then takes over the connection once the response has been sent.
+    ///
+    /// The request must have asked for `protocol` in its `Upgrade` header (RFC 9110 7.8),
+    /// the connection is closed without upgrading if the request body couldn't be drained.
+    ///
+    /// Code example:
+    /// ```rust,no_run
+    /// # use http_tokio::Response;
+    /// let response = Response::build().upgrade("echo", |mut io| async move {
+    ///     let (reader, writer) = io.split();
+    ///     let _ = tokio::io::copy_buf(reader, writer).await;
+    /// });
+    /// ```
+    pub fn upgrade<F, Fut>(mut self, protocol: impl AsRef<str>, on_upgrade: F) -> HttpResponse
+    where
+        F: FnOnce(TcpIO) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.status = StatusCode::SWITCHING_PROTOCOLS;
+        self.inner.headers.insert(("Connection", "upgrade"));
+        self.inner.headers.insert(("Upgrade", protocol));
+        self.inner.headers.remove("Content-Length");
+        self.inner.headers.remove("Transfer-Encoding");
+        self.inner.body = None;
+        self.inner.upgrade = Some(UpgradeHandler(Box::new(move |io| Box::pin(on_upgrade(io)))));
+        self.inner
+    }
+
+    /// Server-Sent Events response streaming `events` as `text/event-stream`,
+    /// with a keep-alive comment every 15 seconds while no event is sent.
+    ///
+    /// The stream ending closes the event stream, clients then reconnect after their retry delay
+    /// and send the ID of the last event they received, see `Request::last_event_id`.
+    pub fn sse<S: Stream<Item = Event> + Send + Sync + Unpin + 'static>(self, events: S) -> HttpResponse {
+        self.sse_keep_alive(events, Some(DEFAULT_KEEP_ALIVE))
+    }
+
+    /// Same as `sse` with a custom keep-alive interval, `None` to never send keep-alive comments.
+    pub fn sse_keep_alive<S: Stream<Item = Event> + Send + Sync + Unpin + 'static>(mut self, events: S, keep_alive: Option<Duration>) -> HttpResponse {
+        self.inner.headers.content_type(ContentType::EventStream);
+        if !self.inner.headers.contains_key("Cache-Control") {
+            self.inner.headers.insert(("Cache-Control", "no-cache"));
+        }
+        self.stream(EventStream::new(events, keep_alive))
+    }
+
     pub async fn file<P: AsRef<Path>>(mut self, path: P) -> Result<HttpResponse, std::io::Error> {
         if !self.inner.headers.contains_key("Content-Type") {
             let content_type = mime_guess::from_path(&path).first_or_octet_stream().to_string();
             self.inner.headers.insert(("Content-Type", &content_type));
         }
         let file = File::open(&path).await?;
-        let stream = ReaderStream::new(file).map(|res| res.map(Bytes::from).map_err(Into::into));
-        Ok(self.stream(stream))
+        let metadata = file.metadata().await?;
+        let len = metadata.len();
+        // validators for conditional requests, the ETag changes with the size or the modification time in nanoseconds
+        // and is strong so that `If-Range` can resume a download with it, compressing the body makes it weak
+        if let Ok(modified) = metadata.modified() {
+            let mtime = modified.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+            if !self.inner.headers.contains_key("Last-Modified") {
+                self.inner.headers.insert(("Last-Modified", HttpDate::from(modified).to_string()));
+            }
+            if !self.inner.headers.contains_key("ETag") {
+                self.inner.headers.insert(("ETag", format!("\"{len:x}-{mtime:x}\"")));
+            }
+        }
+        // the connection answers 304 or sends only the requested ranges according to the request's headers
+        self.inner.headers.insert(("Accept-Ranges", "bytes"));
+        self.inner.headers.insert(("Content-Length", len.to_string()));
+        self.inner.headers.remove("Transfer-Encoding");
+        self.inner.body = Some(Body::File(FileBody::new(file, len)));
+        Ok(self.inner)
     }
 
     pub fn end(self) -> HttpResponse {
@@ -160,6 +296,7 @@ pub enum ResponseError {
     // #[error("Invalid Content-Length: {0}")]
     // InvalidContentLength(String),
 
-    // #[error("Invalid json body")]
-    // Json(#[from] serde_json::Error),
+    #[cfg(feature = "json")]
+    #[error("Invalid json body: {0}")]
+    Json(#[from] serde_json::Error),
 }
\ No newline at end of file
@@ -1,20 +1,33 @@
-use std::{future::Future, net::SocketAddr, time::Duration};
-use crate::{status_code::StatusCode, BodyReader, Request, RequestError, Response, TcpIO};
-use tokio::{net::{TcpStream}, time::timeout};
+use std::{future::{poll_fn, Future}, net::SocketAddr, pin::pin, task::Poll, time::Duration};
+use crate::{conditional, content_encoding::{Compression, ContentDecoder}, limits::Limits, range, status_code::StatusCode, upgrade::OnUpgrade, BodyReader, Method, Request, RequestError, Response, TcpIO};
+use tokio::{io::AsyncBufReadExt, net::{TcpStream}, sync::watch, time::timeout};
+#[cfg(feature = "http2")]
+use super::Http2;
+#[cfg(feature = "tls")]
+use super::Tls;
 use tracing::{info, instrument, warn};
 
 pub struct Connection {
-    io: TcpIO,
+    stream: TcpStream,
     #[allow(unused)]
     addr: SocketAddr,
     keep_alive_timeout: usize,
     keep_alive_max: usize,
+    limits: Limits,
+    decompress_requests: bool,
+    compression: Option<Compression>,
+    #[cfg(feature = "http2")]
+    http2: Option<Http2>,
+    #[cfg(feature = "tls")]
+    tls: Option<Tls>,
     events_handler: Box<dyn ConnectionEventsHandler>,
+    /// set to `true` by the `Server` when it shuts down
+    shutdown: Option<watch::Receiver<bool>>,
 }
 
 impl Connection {
     pub fn new(stream: TcpStream, addr: SocketAddr) -> Self { 
-        Self { keep_alive_timeout: 5, keep_alive_max: 200, io: TcpIO::new(stream), addr, events_handler: Box::new(DefaultConncetionEventsHandler) } 
+        Self { keep_alive_timeout: 5, keep_alive_max: 200, limits: Limits::default(), decompress_requests: false, compression: None, #[cfg(feature = "http2")] http2: None, #[cfg(feature = "tls")] tls: None, stream, addr, events_handler: Box::new(DefaultConncetionEventsHandler), shutdown: None } 
     }
 
     /// Sets the keep-alive timeout in seconds.
@@ -33,10 +46,59 @@ impl Connection {
         self
     }
 
+    /// Sets the size limits enforced on incoming requests.
+    /// 
+    /// Default is `Limits::default()`.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Decompresses request bodies sent with a `Content-Encoding` of `gzip` or `deflate` (and `br` with the `brotli` feature)
+    /// as they are read, the body size limit then applies to the decompressed size,
+    /// which `Limits::max_decompressed_size` bounds even without a body size limit.
+    /// Any other encoding is answered with `415 Unsupported Media Type` without calling the handler.
+    /// 
+    /// Default is `false`, bodies are passed to the handler as received.
+    pub fn decompress_requests(mut self, enabled: bool) -> Self {
+        self.decompress_requests = enabled;
+        self
+    }
+
+    /// Compresses responses with the best coding accepted by the client, see `Compression`.
+    /// 
+    /// Default is no compression.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Serves the connection as HTTP/2 when the client starts with the HTTP/2 preface ("prior knowledge"), see `Http2`.
+    /// 
+    /// Default is HTTP/1.x only.
+    #[cfg(feature = "http2")]
+    pub fn http2(mut self, http2: Http2) -> Self {
+        self.http2 = Some(http2);
+        self
+    }
+
+    /// Serves the connection over TLS, the handshake must complete within the keep-alive timeout, see `Tls`.
+    /// 
+    /// Default is plain TCP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
     /// Sets the events handler for the connection.
     /// 
     /// Code example:
-    /// ```rust
+    /// ```rust,no_run
+    /// # use std::{future::Future, pin::Pin};
+    /// # use http_tokio::{server::{Connection, ConnectionEventsHandler}, RequestError, Response, StatusCode};
+    /// # async fn run(listener: tokio::net::TcpListener) -> std::io::Result<()> {
+    /// # let (stream, addr) = listener.accept().await?;
     /// struct MyEventsHandler;
     /// impl ConnectionEventsHandler for MyEventsHandler {
     ///     fn handle_client_error(&self, err: RequestError, status_code: StatusCode) -> Pin<Box<dyn Future<Output = Response> + Send>> {
@@ -44,25 +106,83 @@ impl Connection {
     ///             Response::build().status(status_code).body(format!("invalid request: {err}"))
     ///         })
     ///     }
-    /// 
+    ///
     ///     fn handle_timeout(&self) -> Pin<Box<dyn Future<Output = Response> + Send>> {
     ///         Box::pin(async move {
     ///             Response::build().status(StatusCode::REQUEST_TIMEOUT).header(("Connection", "close")).body("Request Timeout")
     ///         })
     ///     }
     /// }
-    /// 
+    ///
     /// let connection = Connection::new(stream, addr)
     ///     .events_handler(MyEventsHandler);
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn events_handler(mut self, handler: impl ConnectionEventsHandler + 'static) -> Self {
         self.events_handler = Box::new(handler);
         self
     }
     
+    /// Closes the connection as soon as possible once `shutdown` turns `true`
+    pub(super) fn shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
     #[instrument(skip_all, "new connection", fields(client_address = %self.addr))]
     pub async fn handle_with(self, handler: impl for<'a> ConnectionHandler<'a>) {
-        let mut io = self.io;
+        #[cfg(feature = "tls")]
+        let mut io = match &self.tls {
+            Some(tls) => {
+                #[cfg(feature = "http2")]
+                let http2 = self.http2.is_some();
+                #[cfg(not(feature = "http2"))]
+                let http2 = false;
+                match timeout(Duration::from_secs(self.keep_alive_timeout as u64), tls.accept(self.stream, http2)).await {
+                    Ok(Ok(io)) => io,
+                    Ok(Err(err)) => {
+                        warn!(error = %err, "TLS handshake failed, closing connection");
+                        return;
+                    },
+                    Err(_) => {
+                        info!("TLS handshake timed out after {} seconds, closing connection", self.keep_alive_timeout);
+                        return;
+                    },
+                }
+            },
+            None => TcpIO::new(self.stream),
+        };
+        #[cfg(not(feature = "tls"))]
+        let mut io = TcpIO::new(self.stream);
+
+        #[cfg(feature = "http2")]
+        let http2_shared = |events_handler| super::http2::Shared {
+            limits: self.limits,
+            decompress_requests: self.decompress_requests,
+            compression: self.compression,
+            keep_alive_timeout: Duration::from_secs(self.keep_alive_timeout as u64),
+            events_handler: std::sync::Mutex::new(events_handler),
+            shutdown: self.shutdown.clone(),
+        };
+        #[cfg(feature = "http2")]
+        if let Some(http2) = self.http2 {
+            match timeout(Duration::from_secs(self.keep_alive_timeout as u64), super::http2::has_preface(&mut io)).await {
+                Ok(Ok(true)) => {
+                    super::http2::serve(io, http2, http2_shared(self.events_handler), handler).await;
+                    return;
+                },
+                Ok(_) => {},
+                Err(_) => {
+                    info!("Request timed out after {} seconds, sending timeout response", self.keep_alive_timeout);
+                    let mut res = self.events_handler.handle_timeout().await;
+                    if res.send(&mut io).await.is_err() {
+                        warn!("Error sending response, closing connection");
+                    }
+                    return;
+                },
+            }
+        }
 
         let mut handled_req_count: usize = 0;
 
@@ -70,22 +190,38 @@ impl Connection {
         loop {
             handled_req_count += 1;
             
-            let t_req = timeout(Duration::from_secs(self.keep_alive_timeout as u64), io.receive_request()).await;
+            let receive = async {
+                // a connection idle between requests is closed right away when the server shuts down
+                if !wait_for_request(&mut io, self.shutdown.clone()).await {
+                    return None;
+                }
+                Some(io.receive_request_with(&self.limits).await)
+            };
+            let t_req = timeout(Duration::from_secs(self.keep_alive_timeout as u64), receive).await;
 
             let req_or_early_res = match t_req {
-                Ok(Ok(req)) => RequestOutcome::EarlyResponse(req),
-                Ok(Err(err)) => match err {
+                Ok(None) => {
+                    info!("Server shutting down, closing idle connection");
+                    break;
+                },
+                Ok(Some(Ok(req))) => match (self.limits.max_body_size, req.content_len().await) {
+                    // a declared length over the limit is answered without calling the handler
+                    (Some(max), Some(len)) if len > max => {
+                        warn!(len, limit = max, "Declared request body too large, sending error response and closing connection");
+                        let mut res = self.events_handler.handle_client_error(RequestError::PayloadTooLarge(max), StatusCode::PAYLOAD_TOO_LARGE).await;
+                        res.headers.insert(("Connection", "close"));
+                        res.version = req.version;
+                        RequestOutcome::ValidRequest(res)
+                    },
+                    _ => RequestOutcome::EarlyResponse(req),
+                },
+                Ok(Some(Err(err))) => match err {
                     RequestError::ConnectionClosed => {
                         info!("Connection closed by client, stopping keep-alive loop");
                         break;
                     },
                     _ => {
-                        let status = match err {
-                            RequestError::InvalidHeader(_) => StatusCode::BAD_REQUEST,
-                            RequestError::InvalidContentLength(_) => StatusCode::BAD_REQUEST,
-                            RequestError::UnsupportedHttpVersion(_) => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
-                            _ => StatusCode::INTERNAL_SERVER_ERROR,
-                        };
+                        let status = err.status_code();
                         warn!(error = %err, "Error receiving request, sending error response with status");
                         let mut res = self.events_handler.handle_client_error(err, status).await;
                         res.headers.insert(("Connection", "close"));
@@ -98,14 +234,98 @@ impl Connection {
                 }
             };
 
+            // sender of the request's `OnUpgrade`, and whether the response opens a `CONNECT` tunnel
+            let mut upgrade_tx = None;
+            let mut tunnel = false;
+
+            #[cfg(feature = "http2")]
+            if let (Some(http2), RequestOutcome::EarlyResponse(req)) = (self.http2, &req_or_early_res) {
+                if let Some(request) = super::http2::upgrade_request(req, &io) {
+                    let mut res = Response::build()
+                        .status(StatusCode::SWITCHING_PROTOCOLS)
+                        .header(("Connection", "upgrade"))
+                        .header(("Upgrade", "h2c"))
+                        .end();
+                    res.version = req.version;
+                    if res.send(&mut io).await.is_err() {
+                        warn!("Error sending response, closing connection");
+                        break;
+                    }
+                    info!("Connection upgraded to HTTP/2");
+                    super::http2::serve_upgraded(io, request, http2, http2_shared(self.events_handler), handler).await;
+                    return;
+                }
+            }
+
             let mut res: Response = match req_or_early_res {
                 RequestOutcome::ValidRequest(res) => res,
-                RequestOutcome::EarlyResponse(req) => {
-                    let payload = BodyReader::new(req.content_len().await.unwrap_or(0), io);
-                    let mut res = handler.handle(&req, &payload).await;
-                    if !res.headers.contains_key("Connection") {
-                        let connection = req.headers.get("Connection").cloned().unwrap_or("keep-alive".to_string());
-                        if connection.eq_ignore_ascii_case("close") {
+                RequestOutcome::EarlyResponse(mut req) => {
+                    let mut payload = if req.headers.is_chunked() {
+                        BodyReader::chunked(io)
+                    } else {
+                        BodyReader::new(req.content_len().await.unwrap_or(0), io)
+                    };
+                    if req.expects_continue() {
+                        payload = payload.expect_continue();
+                    }
+                    #[cfg(feature = "json")]
+                    {
+                        payload = payload.content_type(req.headers.get_value("Content-Type").cloned()).max_json_size(self.limits.max_json_size);
+                    }
+                    if let Some(max) = self.limits.max_body_size {
+                        payload.set_max_size(max);
+                    }
+                    if req.method == Method::CONNECT || req.headers.contains_key("Upgrade") {
+                        let (tx, on_upgrade) = OnUpgrade::pair();
+                        req.extensions.insert(on_upgrade).await;
+                        upgrade_tx = Some(tx);
+                    }
+                    let mut res = match content_decoder(&req, self.decompress_requests) {
+                        Ok(decoder) => {
+                            if let Some(decoder) = decoder {
+                                // the handler sees the decoded body, whose length isn't known in advance
+                                payload = payload.content_decoder(decoder, self.limits.max_decompressed_size);
+                                req.headers.remove("Content-Encoding");
+                                req.headers.remove("Content-Length");
+                            }
+                            handler.handle(&req, &payload).await
+                        },
+                        Err(err) => {
+                            warn!(error = %err, "Unsupported request content encoding, sending error response");
+                            let status = err.status_code();
+                            self.events_handler.handle_client_error(err, status).await
+                        },
+                    };
+                    // the rest of the body is read first: an unread chunked body only goes over the limit while it is drained
+                    let mut close = false;
+                    if payload.is_continue_pending() {
+                        // answered without reading: the client never got 100 Continue and won't send the body
+                        info!("Request body not read after \"Expect: 100-continue\", closing connection after response");
+                        close = true;
+                    } else if let Err(err) = payload.drain().await {
+                        // the framing is lost, the connection can't be reused
+                        warn!(error = %err, "Error draining request body, closing connection after response");
+                        close = true;
+                    }
+                    if let Some(limit) = payload.exceeded_limit() {
+                        warn!(limit, "Request body too large, sending error response and closing connection");
+                        let err = RequestError::PayloadTooLarge(limit);
+                        res = self.events_handler.handle_client_error(err, StatusCode::PAYLOAD_TOO_LARGE).await;
+                        res.headers.insert(("Connection", "close"));
+                        res.headers.remove("Keep-Alive");
+                    }
+                    finalize(&req, &mut res, self.compression.as_ref());
+                    if req.method == Method::CONNECT && (200..300).contains(&res.status.code) {
+                        // the tunnel starts right after the head, which has no content (RFC 9110 9.3.6)
+                        tunnel = true;
+                        res.body = None;
+                        res.headers.remove("Content-Length");
+                        res.headers.remove("Transfer-Encoding");
+                    }
+                    res.version = req.version;
+                    let shutting_down = self.shutdown.as_ref().is_some_and(|shutdown| *shutdown.borrow());
+                    if !res.headers.contains_key("Connection") && !tunnel {
+                        if !req.keep_alive() || shutting_down {
                             res.headers.insert(("Connection", "close"));
                             res.headers.remove("Keep-Alive");
                         } else {
@@ -113,9 +333,9 @@ impl Connection {
                             res.headers.insert(("Keep-Alive", &format!("timeout={}, max={}", self.keep_alive_timeout, self.keep_alive_max)));
                         }
                     }
-                    if payload.drain().await.is_err() {
-                        warn!("Error draining request body, closing connection");
-                        break;
+                    if close {
+                        res.headers.insert(("Connection", "close"));
+                        res.headers.remove("Keep-Alive");
                     }
                     io = payload.into_io();
                     res
@@ -126,7 +346,25 @@ impl Connection {
                 warn!("Error sending response, closing connection");
                 break;
             }
-            
+
+            if res.status.code == 101 || tunnel {
+                // Connection is `close` when the request body couldn't be drained, what follows isn't the new protocol
+                if res.headers.contains_token("Connection", "close") {
+                    info!("Request body not drained, closing connection instead of switching protocols");
+                } else if let Some(upgrade) = res.upgrade.take() {
+                    info!(protocol = res.headers.get("Upgrade").unwrap_or_default(), "Connection upgraded, handing it over");
+                    (upgrade.0)(io).await;
+                } else if let Some(upgrade_tx) = upgrade_tx {
+                    info!("Connection upgraded, handing it over to the request's upgrade future");
+                    if upgrade_tx.send(io).is_err() {
+                        info!("Upgrade future dropped, closing connection");
+                    }
+                } else {
+                    info!("Switching protocols without an upgrade handler, closing connection");
+                }
+                break;
+            }
+
             match res.headers.get("Keep-Alive") {
                 _ if handled_req_count >= self.keep_alive_max => {
                     info!("Max keep-alive requests reached, closing connection");
@@ -142,6 +380,49 @@ impl Connection {
     }
 }
 
+/// Waits for the first bytes of the next request, `false` if the server starts shutting down before they arrive
+async fn wait_for_request(io: &mut TcpIO, shutdown: Option<watch::Receiver<bool>>) -> bool {
+    let mut shutdown = pin!(shutdown_signaled(shutdown));
+    let mut data = pin!(io.reader().fill_buf());
+    // read errors and the end of the stream are reported when the request is received
+    poll_fn(|cx| match data.as_mut().poll(cx) {
+        Poll::Ready(_) => Poll::Ready(true),
+        Poll::Pending => shutdown.as_mut().poll(cx).map(|()| false),
+    })
+    .await
+}
+
+/// Resolves once the server starts shutting down, never for a connection served without a shutdown signal
+pub(super) async fn shutdown_signaled(shutdown: Option<watch::Receiver<bool>>) {
+    if let Some(mut shutdown) = shutdown {
+        if shutdown.wait_for(|&down| down).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Decoder for the request's `Content-Encoding`, when request decompression is enabled
+pub(super) fn content_decoder(req: &Request, enabled: bool) -> Result<Option<ContentDecoder>, RequestError> {
+    match req.headers.get_value("Content-Encoding") {
+        Some(encoding) if enabled => ContentDecoder::new(&encoding.to_string()).map_err(RequestError::UnsupportedContentEncoding),
+        _ => Ok(None),
+    }
+}
+
+/// Adapts the handler's response to the request: validators, byte ranges, compression and `HEAD`
+pub(super) fn finalize(req: &Request, res: &mut Response, compression: Option<&Compression>) {
+    conditional::apply(req, res);
+    range::apply(req, res);
+    if let Some(compression) = compression {
+        compression.apply(req, res);
+    }
+    if req.method == Method::HEAD {
+        // same headers as GET, Content-Length included, but no content (RFC 9110 9.3.2)
+        res.body = None;
+    }
+}
+
 pub trait ConnectionHandler<'a>: Clone + Send + 'static {
     fn handle(&'a self, request: &'a Request, payload: &'a BodyReader) -> std::pin::Pin<Box<dyn Future<Output = Response> + Send + 'a>>;
 }
@@ -186,4 +467,92 @@ impl ConnectionEventsHandler for DefaultConncetionEventsHandler {}
 enum RequestOutcome<L, R> {
     EarlyResponse(L),
     ValidRequest(R)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+    use crate::block_on;
+    use super::*;
+
+    async fn read_body(_req: &Request, body: &BodyReader) -> Response {
+        match body.read_all().await {
+            Ok(body) => Response::build().body(format!("read {} bytes", body.len())),
+            Err(err) => Response::build().status(StatusCode::BAD_REQUEST).body(err.to_string()),
+        }
+    }
+
+    async fn unreachable(_req: &Request, _body: &BodyReader) -> Response {
+        panic!("the handler should not be called");
+    }
+
+    /// Sends `raw` to a connection configured by `configure` and returns what it answered until it closed
+    async fn exchange(raw: &[u8], configure: impl FnOnce(Connection) -> Connection) -> String {
+        exchange_with(raw, configure, read_body).await
+    }
+
+    async fn exchange_with(raw: &[u8], configure: impl FnOnce(Connection) -> Connection, handler: impl for<'a> ConnectionHandler<'a>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let server = tokio::spawn(configure(Connection::new(stream, addr)).handle_with(handler));
+        client.write_all(raw).await.unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0; 4096];
+        // a connection closed with unread input is reset, the response is read up to the reset
+        while let Ok(len @ 1..) = client.read(&mut buf).await {
+            response.extend_from_slice(&buf[..len]);
+        }
+        server.await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn gzip_zeros(len: usize) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+        let zeros = [0; 64 * 1024];
+        for _ in 0..len / zeros.len() {
+            encoder.write_all(&zeros).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    fn gzip_request(body: &[u8]) -> Vec<u8> {
+        let head = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+        [head.as_bytes(), body].concat()
+    }
+
+    #[test]
+    fn decompressed_size_is_bounded_by_default() {
+        // 64 MiB of zeros compress to about 64 KiB
+        let bomb = gzip_zeros(64 * 1024 * 1024);
+        let response = block_on(exchange(&gzip_request(&bomb), |connection| connection.decompress_requests(true)));
+        assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    }
+
+    #[test]
+    fn decompressed_size_limit_is_configurable() {
+        let body = gzip_zeros(1024 * 1024);
+        let response = block_on(exchange(&gzip_request(&body), |connection| {
+            connection.decompress_requests(true).limits(Limits::default().max_decompressed_size(512 * 1024))
+        }));
+        assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+        let response = block_on(exchange(&gzip_request(&body), |connection| connection.decompress_requests(true)));
+        assert!(response.starts_with("HTTP/1.1 200 ") && response.ends_with("read 1048576 bytes"), "{response}");
+    }
+
+    #[test]
+    fn declared_length_over_the_limit_is_rejected_before_the_handler() {
+        let raw = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1000\r\nExpect: 100-continue\r\n\r\n";
+        let response = block_on(exchange_with(raw, |connection| connection.limits(Limits::default().max_body_size(10)), unreachable));
+        assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+        assert!(response.contains("Connection: close\r\n"), "{response}");
+    }
+
+    #[test]
+    fn chunked_body_over_the_limit_is_rejected_once_read() {
+        let raw = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n14\r\n01234567890123456789\r\n0\r\n\r\n";
+        let response = block_on(exchange(raw, |connection| connection.limits(Limits::default().max_body_size(10))));
+        assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    }
+}
@@ -0,0 +1,627 @@
+use std::{future::{poll_fn, Future}, io::SeekFrom, pin::{pin, Pin}, sync::{Mutex, PoisonError}, task::Poll, time::Duration};
+use bytes::Bytes;
+use h2::{server::SendResponse, RecvStream, SendStream};
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt}, sync::watch, time::{sleep, timeout, Instant}};
+use tracing::{info, instrument, warn};
+use crate::{body::{Body, FileBody, FileSegment}, limits::Limits, status_code::StatusCode, uri::UriForm, BodyReader, Compression, Method, Request, RequestError, Response, ResponseError, TcpIO, Version};
+use super::{connection::{content_decoder, finalize, shutdown_signaled}, ConnectionEventsHandler, ConnectionHandler};
+
+/// Sent by HTTP/2 clients before anything else (RFC 9113 3.4)
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Largest piece of a file read at once before being sent as DATA frames
+const FILE_CHUNK_LEN: usize = 16 * 1024;
+
+/// Frame payload size every endpoint accepts, whatever its settings (RFC 9113 4.2)
+const MIN_MAX_FRAME_SIZE: usize = 16 * 1024;
+
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_CONTINUATION: u8 = 0x9;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// HTTP/2 settings, connections whose client starts with the HTTP/2 preface are served as HTTP/2 (RFC 9113 3.3),
+/// as are cleartext HTTP/1.1 connections switched with `Upgrade: h2c` (RFC 7540 3.2) by a request without content.
+///
+/// Requests are handled concurrently, one per stream, by the same handlers as HTTP/1.1 requests.
+/// The connection is closed with GOAWAY after the keep-alive timeout without any stream,
+/// or when the server shuts down, see `Server::serve_with_graceful_shutdown`.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::{server::{Http2, Server}, BodyReader, Request, Response};
+/// # async fn handler(_req: &Request, _body: &BodyReader) -> Response {
+/// #     Response::build().body("hello")
+/// # }
+/// # async fn run() -> std::io::Result<()> {
+/// Server::bind("0.0.0.0:8080").await?
+///     .http2(Http2::default().max_concurrent_streams(250))
+///     .serve(handler)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Http2 {
+    max_concurrent_streams: u32,
+    initial_window_size: u32,
+    initial_connection_window_size: u32,
+    max_frame_size: u32,
+}
+
+impl Default for Http2 {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: 100,
+            initial_window_size: 1024 * 1024,
+            initial_connection_window_size: 4 * 1024 * 1024,
+            max_frame_size: 16 * 1024,
+        }
+    }
+}
+
+impl Http2 {
+    /// Sets the maximum number of streams, i.e. requests, the client can open at the same time.
+    ///
+    /// Default is 100 streams, streams over the limit are refused.
+    pub fn max_concurrent_streams(mut self, max: u32) -> Self {
+        self.max_concurrent_streams = max;
+        self
+    }
+
+    /// Sets the flow control window of each stream: the request body bytes the client can send before the handler reads them.
+    ///
+    /// Default is 1 MiB.
+    pub fn initial_window_size(mut self, size: u32) -> Self {
+        self.initial_window_size = size;
+        self
+    }
+
+    /// Sets the flow control window of the whole connection, shared by the request bodies of every stream.
+    ///
+    /// Default is 4 MiB.
+    pub fn initial_connection_window_size(mut self, size: u32) -> Self {
+        self.initial_connection_window_size = size;
+        self
+    }
+
+    /// Sets the largest frame payload the client can send, between 16 KiB and 16 MiB.
+    ///
+    /// Default is 16 KiB.
+    pub fn max_frame_size(mut self, size: u32) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+}
+
+/// Whether the first bytes sent by the client are the HTTP/2 preface, nothing is consumed
+pub(super) async fn has_preface(io: &mut TcpIO) -> std::io::Result<bool> {
+    // HTTP/1.1 methods never start like the preface, its first 4 bytes are enough to tell
+    let mut seen = Vec::new();
+    let is_preface = loop {
+        let buf = io.reader().fill_buf().await?;
+        if buf.is_empty() {
+            break false;
+        }
+        let len = (seen.len() + buf.len()).min(PREFACE.len());
+        let start = &buf[..len - seen.len()];
+        if seen.iter().chain(start).ne(&PREFACE[..len]) {
+            break false;
+        }
+        if len >= 4 {
+            break true;
+        }
+        // the preface may arrive in several segments, the bytes are set aside until the next ones come
+        seen.extend_from_slice(buf);
+        let consumed = buf.len();
+        io.reader().consume(consumed);
+    };
+    if !seen.is_empty() {
+        io.unread(&seen);
+    }
+    Ok(is_preface)
+}
+
+/// HEADERS frames replaying an HTTP/1.1 request that asks for `Upgrade: h2c` as stream 1 (RFC 7540 3.2),
+/// `None` when the upgrade must be ignored and the request answered over HTTP/1.1.
+///
+/// Only requests without content are upgraded: their body would have to be replayed against the new flow control windows.
+pub(super) fn upgrade_request(req: &Request, io: &TcpIO) -> Option<Bytes> {
+    // h2c is HTTP/2 over cleartext TCP, over TLS the protocol is negotiated with ALPN instead
+    if io.is_tls() || req.version != Version::Http11 || req.method == Method::CONNECT {
+        return None;
+    }
+    let connection = |token| req.headers.contains_token("Connection", token);
+    if !req.headers.contains_token("Upgrade", "h2c") || !connection("upgrade") || !connection("HTTP2-Settings") {
+        return None;
+    }
+    match req.headers.get_all("HTTP2-Settings").map(Vec::as_slice) {
+        Some([settings]) if is_settings_payload(settings.as_bytes()) => {},
+        _ => return None,
+    }
+    if req.headers.is_chunked() || req.headers.get("Content-Length").is_some_and(|length| length != "0") {
+        return None;
+    }
+
+    let path = match req.uri.form() {
+        UriForm::Absolute => format!("{}{}", req.uri.path(), req.uri.query().map(|query| format!("?{query}")).unwrap_or_default()),
+        _ => req.uri.raw().to_string(),
+    };
+    let authority = req.uri.authority().or_else(|| req.headers.get("Host")).unwrap_or_default();
+    let mut block = Vec::new();
+    for (name, value) in [(":method", req.method.as_str()), (":scheme", "http"), (":path", &path), (":authority", authority)] {
+        encode_field(&mut block, name.as_bytes(), value.as_bytes());
+    }
+    for (name, values) in req.headers.iter() {
+        let name = name.as_str().to_ascii_lowercase();
+        // connection-specific fields are malformed in HTTP/2 (RFC 9113 8.2.2), Host became :authority
+        if ["connection", "upgrade", "http2-settings", "keep-alive", "proxy-connection", "transfer-encoding", "te", "host"].contains(&name.as_str()) {
+            continue;
+        }
+        for value in values {
+            encode_field(&mut block, name.as_bytes(), value.as_bytes());
+        }
+    }
+
+    // the block is split at the smallest maximum frame size, HEADERS then CONTINUATION frames (RFC 9113 6.10)
+    let mut frames = Vec::with_capacity(block.len() + 9 * (block.len() / MIN_MAX_FRAME_SIZE + 1));
+    let mut chunks = block.chunks(MIN_MAX_FRAME_SIZE).peekable();
+    let mut frame_type = FRAME_HEADERS;
+    let mut flags = FLAG_END_STREAM;
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+        frames.extend_from_slice(&(chunk.len() as u32).to_be_bytes()[1..]);
+        frames.extend_from_slice(&[frame_type, flags]);
+        frames.extend_from_slice(&1u32.to_be_bytes());
+        frames.extend_from_slice(chunk);
+        frame_type = FRAME_CONTINUATION;
+        flags = 0;
+    }
+    Some(frames.into())
+}
+
+/// `HTTP2-Settings` holds a SETTINGS payload, 6 bytes per setting, in base64url without padding
+fn is_settings_payload(value: &[u8]) -> bool {
+    let decoded_len = value.len() / 4 * 3 + (value.len() % 4).saturating_sub(1);
+    value.len() % 4 != 1 && decoded_len.is_multiple_of(6) && value.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'_')
+}
+
+/// HPACK literal field line without indexing and with a literal name, no Huffman coding (RFC 7541 6.2.2)
+fn encode_field(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    for string in [name, value] {
+        encode_integer(block, string.len(), 7);
+        block.extend_from_slice(string);
+    }
+}
+
+/// HPACK integer with an `prefix_bits` bits prefix, the flags of the prefix byte left clear (RFC 7541 5.1)
+fn encode_integer(block: &mut Vec<u8>, mut value: usize, prefix_bits: u32) {
+    let max_prefix = (1 << prefix_bits) - 1;
+    if value < max_prefix {
+        block.push(value as u8);
+        return;
+    }
+    block.push(max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Serves a connection switched from HTTP/1.1 with `Upgrade: h2c` once `101 Switching Protocols` has been sent,
+/// `request` being the frames from `upgrade_request`
+pub(super) async fn serve_upgraded(mut io: TcpIO, request: Bytes, settings: Http2, shared: Shared, handler: impl for<'a> ConnectionHandler<'a>) {
+    // the client opens with the preface and its SETTINGS frame, the request is replayed right after them
+    // so that the connection sees it as the first stream
+    let opening = match timeout(shared.keep_alive_timeout, read_opening(&mut io)).await {
+        Ok(Ok(Some(opening))) => opening,
+        Ok(Ok(None)) => {
+            warn!("Invalid HTTP/2 preface after h2c upgrade, closing connection");
+            return;
+        },
+        Ok(Err(err)) => {
+            warn!(error = %err, "Error reading HTTP/2 preface after h2c upgrade, closing connection");
+            return;
+        },
+        Err(_) => {
+            info!("HTTP/2 preface timed out after h2c upgrade, closing connection");
+            return;
+        },
+    };
+    let mut replayed = opening;
+    replayed.extend_from_slice(&request);
+    io.unread(&replayed);
+    serve(io, settings, shared, handler).await;
+}
+
+/// The client preface and the SETTINGS frame that must follow it, `None` if they are malformed
+async fn read_opening(io: &mut TcpIO) -> std::io::Result<Option<Vec<u8>>> {
+    let mut opening = vec![0; PREFACE.len() + 9];
+    io.reader().read_exact(&mut opening).await?;
+    let (preface, frame_head) = opening.split_at(PREFACE.len());
+    let len = u32::from_be_bytes([0, frame_head[0], frame_head[1], frame_head[2]]) as usize;
+    let stream_id = u32::from_be_bytes([frame_head[5], frame_head[6], frame_head[7], frame_head[8]]) & 0x7fff_ffff;
+    if preface != PREFACE || frame_head[3] != FRAME_SETTINGS || stream_id != 0 || len > MIN_MAX_FRAME_SIZE {
+        return Ok(None);
+    }
+    let start = opening.len();
+    opening.resize(start + len, 0);
+    io.reader().read_exact(&mut opening[start..]).await?;
+    Ok(Some(opening))
+}
+
+/// Connection settings used by every stream
+pub(super) struct Shared {
+    pub limits: Limits,
+    pub decompress_requests: bool,
+    pub compression: Option<Compression>,
+    pub keep_alive_timeout: Duration,
+    /// locked only to create the error response futures, the streams share it
+    pub events_handler: Mutex<Box<dyn ConnectionEventsHandler>>,
+    pub shutdown: Option<watch::Receiver<bool>>,
+}
+
+impl Shared {
+    fn client_error(&self, err: RequestError, status: StatusCode) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        self.events_handler.lock().unwrap_or_else(PoisonError::into_inner).handle_client_error(err, status)
+    }
+}
+
+/// Serves the streams of an HTTP/2 connection until the client closes it or it stays idle for the keep-alive timeout
+#[instrument(skip_all, "http2")]
+pub(super) async fn serve(io: TcpIO, settings: Http2, shared: Shared, handler: impl for<'a> ConnectionHandler<'a>) {
+    let mut builder = h2::server::Builder::new();
+    builder
+        .max_concurrent_streams(settings.max_concurrent_streams)
+        .initial_window_size(settings.initial_window_size)
+        .initial_connection_window_size(settings.initial_connection_window_size)
+        .max_frame_size(settings.max_frame_size)
+        .max_header_list_size(shared.limits.max_head_len.try_into().unwrap_or(u32::MAX));
+    let mut connection = match timeout(shared.keep_alive_timeout, builder.handshake::<_, Bytes>(io)).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(err)) => {
+            warn!(error = %err, "HTTP/2 handshake failed, closing connection");
+            return;
+        },
+        Err(_) => {
+            info!("HTTP/2 handshake timed out, closing connection");
+            return;
+        },
+    };
+
+    // the streams are polled along with the connection, which sends and receives their frames
+    let mut streams: Vec<Pin<Box<dyn Future<Output = ()> + Send + '_>>> = Vec::new();
+    let mut idle = Box::pin(sleep(shared.keep_alive_timeout));
+    let mut shutdown = pin!(shutdown_signaled(shared.shutdown.clone()));
+    let mut closing = false;
+    loop {
+        let accepted = poll_fn(|cx| {
+            let count = streams.len();
+            streams.retain_mut(|stream| stream.as_mut().poll(cx).is_pending());
+            if streams.len() < count {
+                idle.as_mut().reset(Instant::now() + shared.keep_alive_timeout);
+            }
+            if streams.is_empty() && !closing && idle.as_mut().poll(cx).is_ready() {
+                info!("HTTP/2 connection idle, sending GOAWAY");
+                connection.graceful_shutdown();
+                closing = true;
+            }
+            // streams in progress are completed, the client opens new ones on another connection
+            if !closing && shutdown.as_mut().poll(cx).is_ready() {
+                info!("Server shutting down, sending GOAWAY");
+                connection.graceful_shutdown();
+                closing = true;
+            }
+            match connection.poll_accept(cx) {
+                Poll::Pending => Poll::Pending,
+                accepted => accepted,
+            }
+        })
+        .await;
+        match accepted {
+            Some(Ok((request, respond))) => {
+                idle.as_mut().reset(Instant::now() + shared.keep_alive_timeout);
+                streams.push(Box::pin(serve_stream(handler.clone(), request, respond, &shared)));
+            },
+            Some(Err(err)) => {
+                warn!(error = %err, "HTTP/2 connection error, closing connection");
+                break;
+            },
+            None => {
+                info!("HTTP/2 connection closed");
+                break;
+            },
+        }
+    }
+    // the connection is gone, responses can't be sent anymore but handlers still run to completion
+    for stream in streams {
+        stream.await;
+    }
+}
+
+async fn serve_stream<H: for<'a> ConnectionHandler<'a>>(handler: H, request: http::Request<RecvStream>, respond: SendResponse<Bytes>, shared: &Shared) {
+    let (parts, body) = request.into_parts();
+    let mut req = match Request::from_http2(&parts).await {
+        Ok(req) => req,
+        Err(err) => {
+            warn!(error = %err, "Invalid HTTP/2 request, sending error response");
+            let status = err.status_code();
+            let res = shared.client_error(err, status).await;
+            if let Err(err) = send(res, respond).await {
+                warn!(error = %err, "Error sending HTTP/2 response");
+            }
+            return;
+        },
+    };
+
+    let mut payload = BodyReader::http2(body);
+    #[cfg(feature = "json")]
+    {
+        payload = payload.content_type(req.headers.get_value("Content-Type").cloned()).max_json_size(shared.limits.max_json_size);
+    }
+    if let Some(max) = shared.limits.max_body_size {
+        payload.set_max_size(max);
+    }
+    let mut res = match content_decoder(&req, shared.decompress_requests) {
+        Ok(decoder) => {
+            if let Some(decoder) = decoder {
+                payload = payload.content_decoder(decoder, shared.limits.max_decompressed_size);
+                req.headers.remove("Content-Encoding");
+                req.headers.remove("Content-Length");
+            }
+            handler.handle(&req, &payload).await
+        },
+        Err(err) => {
+            warn!(error = %err, "Unsupported request content encoding, sending error response");
+            let status = err.status_code();
+            shared.client_error(err, status).await
+        },
+    };
+    if let Some(limit) = payload.exceeded_limit() {
+        warn!(limit, "Request body too large, sending error response");
+        res = shared.client_error(RequestError::PayloadTooLarge(limit), StatusCode::PAYLOAD_TOO_LARGE).await;
+    }
+    finalize(&req, &mut res, shared.compression.as_ref());
+    res.version = req.version;
+    // an unread request body is discarded by resetting its side of the stream, there is no framing to preserve
+    drop(payload);
+
+    if let Err(err) = send(res, respond).await {
+        warn!(error = %err, "Error sending HTTP/2 response");
+    }
+}
+
+/// Sends the response head and body as HEADERS and DATA frames, as fast as the client's flow control window allows
+async fn send(mut res: Response, mut respond: SendResponse<Bytes>) -> Result<(), ResponseError> {
+    let mut head = http::Response::builder().status(res.status.code);
+    for (name, values) in res.headers.iter() {
+        // connection-specific fields are malformed in HTTP/2 (RFC 9113 8.2.2)
+        if ["Connection", "Keep-Alive", "Proxy-Connection", "Transfer-Encoding", "Upgrade"].contains(&name.as_str()) {
+            continue;
+        }
+        for value in values {
+            head = head.header(name.as_str(), value.as_bytes());
+        }
+    }
+    let head = head.body(()).map_err(std::io::Error::other)?;
+
+    let Some(body) = res.body.take() else {
+        respond.send_response(head, true).map_err(std::io::Error::other)?;
+        return Ok(());
+    };
+    let mut stream = respond.send_response(head, false).map_err(std::io::Error::other)?;
+    let result = match body {
+        Body::Bytes(bytes) => send_data(&mut stream, bytes).await,
+        Body::Stream(mut body) => {
+            let mut result = Ok(());
+            while let Some(chunk) = tokio_stream::StreamExt::next(&mut body).await {
+                result = match chunk {
+                    Ok(chunk) => send_data(&mut stream, chunk).await,
+                    Err(err) => Err(err),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            result
+        },
+        Body::File(file) => send_file(&mut stream, file).await,
+    };
+    match result {
+        Ok(()) => stream.send_data(Bytes::new(), true).map_err(|err| std::io::Error::other(err).into()),
+        Err(err) => {
+            // the client must not take the truncated content for the whole response
+            stream.send_reset(h2::Reason::INTERNAL_ERROR);
+            Err(err)
+        },
+    }
+}
+
+async fn send_file(stream: &mut SendStream<Bytes>, FileBody { mut file, len, segments, tail }: FileBody) -> Result<(), ResponseError> {
+    let segments = segments.unwrap_or_else(|| vec![FileSegment { head: Bytes::new(), start: 0, len }]);
+    for segment in segments {
+        send_data(stream, segment.head).await?;
+        file.seek(SeekFrom::Start(segment.start)).await?;
+        let mut remaining = segment.len;
+        while remaining > 0 {
+            let mut buf = vec![0; remaining.min(FILE_CHUNK_LEN as u64) as usize];
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                // the file shrank since the response was built, Content-Length can't be honored
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            buf.truncate(read);
+            remaining -= read as u64;
+            send_data(stream, buf.into()).await?;
+        }
+    }
+    send_data(stream, tail).await
+}
+
+/// Sends `data` in as many DATA frames as the flow control windows require (RFC 9113 5.2)
+async fn send_data(stream: &mut SendStream<Bytes>, mut data: Bytes) -> Result<(), ResponseError> {
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+        let capacity = match poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(capacity) => capacity.map_err(std::io::Error::other)?,
+            None => return Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into()),
+        };
+        if capacity == 0 {
+            continue;
+        }
+        stream.send_data(data.split_to(capacity.min(data.len())), false).map_err(std::io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}};
+    use crate::block_on;
+    use super::*;
+
+    const UPGRADE: &str = "Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n";
+
+    /// HEADERS and CONTINUATION frames `upgrade_request` makes of the request in `raw`
+    async fn upgrade(raw: &str) -> Option<Bytes> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let mut io = TcpIO::new(server);
+        let req = io.receive_request().await.unwrap();
+        upgrade_request(&req, &io)
+    }
+
+    /// Type, flags, stream ID and payload of every frame
+    fn frames(mut bytes: &[u8]) -> Vec<(u8, u8, u32, &[u8])> {
+        let mut frames = Vec::new();
+        while !bytes.is_empty() {
+            let len = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+            let stream_id = u32::from_be_bytes(bytes[5..9].try_into().unwrap());
+            frames.push((bytes[3], bytes[4], stream_id, &bytes[9..9 + len]));
+            bytes = &bytes[9 + len..];
+        }
+        frames
+    }
+
+    fn decode_integer(block: &mut &[u8], prefix_bits: u32) -> usize {
+        let max_prefix = (1 << prefix_bits) - 1;
+        let mut value = (block[0] & max_prefix as u8) as usize;
+        *block = &block[1..];
+        if value < max_prefix {
+            return value;
+        }
+        let mut shift = 0;
+        loop {
+            let byte = block[0];
+            *block = &block[1..];
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    /// Fields of a block of literal field lines without indexing
+    fn decode_fields(mut block: &[u8]) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        while !block.is_empty() {
+            assert_eq!(block[0], 0, "literal without indexing and with a literal name");
+            block = &block[1..];
+            let mut string = || {
+                let len = decode_integer(&mut block, 7);
+                let string = String::from_utf8(block[..len].to_vec()).unwrap();
+                block = &block[len..];
+                string
+            };
+            fields.push((string(), string()));
+        }
+        fields
+    }
+
+    #[test]
+    fn encodes_integers() {
+        // RFC 7541 C.1
+        for (value, prefix_bits, encoded) in [(10, 5, &[10][..]), (1337, 5, &[31, 154, 10]), (42, 8, &[42]), (127, 7, &[127, 0]), (126, 7, &[126])] {
+            let mut block = Vec::new();
+            encode_integer(&mut block, value, prefix_bits);
+            assert_eq!(block, encoded, "{value}");
+            assert_eq!(decode_integer(&mut &block[..], prefix_bits), value);
+        }
+    }
+
+    #[test]
+    fn replays_the_request_as_stream_1() {
+        block_on(async {
+            let raw = format!("GET /a%20b?c=d HTTP/1.1\r\nHost: example.com\r\n{UPGRADE}Accept: text/html\r\nX-Multi: 1\r\nX-Multi: 2\r\nKeep-Alive: timeout=5\r\n\r\n");
+            let request = upgrade(&raw).await.unwrap();
+            let frames = frames(&request);
+            assert_eq!(frames.len(), 1);
+            let (frame_type, flags, stream_id, block) = frames[0];
+            assert_eq!((frame_type, flags, stream_id), (FRAME_HEADERS, FLAG_END_STREAM | FLAG_END_HEADERS, 1));
+
+            let fields = decode_fields(block);
+            let field = |name: &str, value: &str| (name.to_owned(), value.to_owned());
+            assert_eq!(fields[..4], [field(":method", "GET"), field(":scheme", "http"), field(":path", "/a%20b?c=d"), field(":authority", "example.com")]);
+            let mut rest = fields[4..].to_vec();
+            rest.sort();
+            assert_eq!(rest, [field("accept", "text/html"), field("x-multi", "1"), field("x-multi", "2")]);
+        });
+    }
+
+    #[test]
+    fn uses_the_authority_of_an_absolute_target() {
+        block_on(async {
+            let raw = format!("GET http://other.example:8080/p?q HTTP/1.1\r\nHost: example.com\r\n{UPGRADE}\r\n");
+            let request = upgrade(&raw).await.unwrap();
+            let fields = decode_fields(frames(&request)[0].3);
+            assert_eq!(fields[2], (":path".to_owned(), "/p?q".to_owned()));
+            assert_eq!(fields[3], (":authority".to_owned(), "other.example:8080".to_owned()));
+        });
+    }
+
+    #[test]
+    fn splits_large_blocks_into_continuation_frames() {
+        block_on(async {
+            let headers: String = (0..10).map(|i| format!("X-Large-{i}: {}\r\n", "v".repeat(4000))).collect();
+            let raw = format!("GET / HTTP/1.1\r\nHost: a\r\n{UPGRADE}{headers}\r\n");
+            let request = upgrade(&raw).await.unwrap();
+            let frames = frames(&request);
+            let layout: Vec<_> = frames.iter().map(|&(frame_type, flags, stream_id, payload)| (frame_type, flags, stream_id, payload.len())).collect();
+            let block: Vec<u8> = frames.iter().flat_map(|frame| frame.3.iter().copied()).collect();
+            assert_eq!(layout[..2], [(FRAME_HEADERS, FLAG_END_STREAM, 1, MIN_MAX_FRAME_SIZE), (FRAME_CONTINUATION, 0, 1, MIN_MAX_FRAME_SIZE)]);
+            assert_eq!(layout[2], (FRAME_CONTINUATION, FLAG_END_HEADERS, 1, block.len() - 2 * MIN_MAX_FRAME_SIZE));
+            let fields = decode_fields(&block);
+            assert_eq!(fields.len(), 4 + 10);
+            assert!(fields[4..].iter().all(|(name, value)| name.starts_with("x-large-") && value.len() == 4000));
+        });
+    }
+
+    #[test]
+    fn ignores_requests_that_cant_be_upgraded() {
+        block_on(async {
+            for raw in [
+                "GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n".to_owned(),
+                "GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n\r\n".to_owned(),
+                "GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: websocket\r\nHTTP2-Settings: AAMAAABk\r\n\r\n".to_owned(),
+                format!("GET / HTTP/1.1\r\nHost: a\r\n{UPGRADE}HTTP2-Settings: AAMAAABk\r\n\r\n"),
+                "GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAAB\r\n\r\n".to_owned(),
+                "GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMA+ABk\r\n\r\n".to_owned(),
+                format!("POST / HTTP/1.1\r\nHost: a\r\n{UPGRADE}Content-Length: 5\r\n\r\n"),
+                format!("POST / HTTP/1.1\r\nHost: a\r\n{UPGRADE}Transfer-Encoding: chunked\r\n\r\n"),
+                format!("GET / HTTP/1.0\r\nHost: a\r\n{UPGRADE}\r\n"),
+            ] {
+                assert!(upgrade(&raw).await.is_none(), "{raw:?}");
+            }
+            assert!(upgrade(&format!("POST / HTTP/1.1\r\nHost: a\r\n{UPGRADE}Content-Length: 0\r\n\r\n")).await.is_some());
+        });
+    }
+}
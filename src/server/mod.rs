@@ -1,5 +1,15 @@
 mod connection;
+#[cfg(feature = "http2")]
+mod http2;
+mod serve_dir;
 mod server;
+#[cfg(feature = "tls")]
+mod tls;
 
 pub use connection::{Connection, ConnectionHandler, ConnectionEventsHandler};
-pub use server::{run_server, ServerHandler};
\ No newline at end of file
+#[cfg(feature = "http2")]
+pub use http2::Http2;
+pub use serve_dir::ServeDir;
+pub use server::{run_server, Server, ServerHandler};
+#[cfg(feature = "tls")]
+pub use tls::{Tls, TlsError};
\ No newline at end of file
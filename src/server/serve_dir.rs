@@ -0,0 +1,289 @@
+use std::{fmt::Write as _, io::ErrorKind, path::{Component, Path, PathBuf}};
+use crate::{content_encoding, content_type::ContentType, server::{ConnectionHandler, ServerHandler}, status_code::StatusCode, BodyReader, Method, Request, Response};
+
+/// Handler serving the files of a directory, the request path is resolved relative to `root`.
+///
+/// Paths that would leave `root`, through `..` segments or symbolic links, are answered with `403 Forbidden`.
+/// File responses carry validators and honor conditional and range requests, see `ResponseBuilder::file`.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::server::{run_server, ServeDir};
+/// # async fn run() -> std::io::Result<()> {
+/// let static_files = ServeDir::new("./public")
+///     .directory_listing(true)
+///     .precompressed(true)
+///     .cache_control("public, max-age=3600");
+///
+/// run_server("0.0.0.0:8080", static_files).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServeDir {
+    root: PathBuf,
+    index_file: Option<String>,
+    directory_listing: bool,
+    precompressed: bool,
+    cache_control: Option<String>,
+}
+
+/// Precompressed siblings in order of preference, with the coding they are encoded with
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+impl ServeDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into(), index_file: Some("index.html".to_string()), directory_listing: false, precompressed: false, cache_control: None }
+    }
+
+    /// File served for requests to a directory, `None` to never look for one.
+    ///
+    /// Default is `index.html`.
+    pub fn index_file(mut self, name: Option<impl Into<String>>) -> Self {
+        self.index_file = name.map(Into::into);
+        self
+    }
+
+    /// Answers requests to a directory without an index file with an HTML listing of its entries instead of `404 Not Found`.
+    ///
+    /// Default is `false`.
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// Serves `file.br` or `file.gz` in place of `file` when they exist and the client accepts the coding,
+    /// files are then sent with `Content-Encoding` and the content type of the original file.
+    ///
+    /// Default is `false`.
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// `Cache-Control` header value added to file responses, e.g. `public, max-age=3600`.
+    ///
+    /// Default is none.
+    pub fn cache_control(mut self, value: impl Into<String>) -> Self {
+        self.cache_control = Some(value.into());
+        self
+    }
+
+    /// Answers a request with the file or directory its path points to
+    pub async fn serve(&self, req: &Request) -> Response {
+        if req.method != Method::GET && req.method != Method::HEAD {
+            return Response::build().status(StatusCode::METHOD_NOT_ALLOWED).header(("Allow", "GET, HEAD")).body("Method Not Allowed");
+        }
+        let Some(relative) = relative_path(req.uri.path()) else {
+            return forbidden();
+        };
+        let root = match tokio::fs::canonicalize(&self.root).await {
+            Ok(root) => root,
+            Err(err) => return io_error(err),
+        };
+        let path = match inside_root(&root, &root.join(relative)).await {
+            Ok(Some(path)) => path,
+            Ok(None) => return forbidden(),
+            Err(err) => return io_error(err),
+        };
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(err) => return io_error(err),
+        };
+        if !metadata.is_dir() {
+            return self.serve_file(req, &root, &path).await;
+        }
+
+        // relative links of the index file and the listing resolve against the directory only with the trailing slash,
+        // the redirect is relative too: a path starting with `//` would otherwise be taken as another host
+        if !req.uri.path().ends_with('/') {
+            let name = req.uri.path().rsplit('/').next().unwrap_or_default();
+            let mut location = format!("./{}/", percent_encode_path(name));
+            if let Some(query) = req.uri.query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return Response::build().status(StatusCode::MOVED_PERMANENTLY).header(("Location", location)).body("Moved Permanently");
+        }
+        if let Some(index_file) = &self.index_file {
+            match inside_root(&root, &path.join(index_file)).await {
+                Ok(Some(index)) if tokio::fs::metadata(&index).await.is_ok_and(|metadata| metadata.is_file()) => {
+                    return self.serve_file(req, &root, &index).await;
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => return forbidden(),
+                Err(err) if err.kind() == ErrorKind::NotFound => {},
+                Err(err) => return io_error(err),
+            }
+        }
+        if self.directory_listing {
+            return match listing(&path, req.uri.path(), path != root).await {
+                Ok(html) => Response::build().content_type(ContentType::Html).body(html),
+                Err(err) => io_error(err),
+            };
+        }
+        not_found()
+    }
+
+    /// Serves `path`, already resolved inside `root`
+    async fn serve_file(&self, req: &Request, root: &Path, path: &Path) -> Response {
+        let mut builder = Response::build();
+        if let Some(cache_control) = &self.cache_control {
+            builder = builder.header(("Cache-Control", cache_control));
+        }
+        if self.precompressed {
+            // the response depends on Accept-Encoding even when the original file is sent
+            builder = builder.header(("Vary", "Accept-Encoding"));
+            if let Some((coding, sibling)) = self.precompressed_sibling(req, root, path).await {
+                let content_type = mime_guess::from_path(path).first_or_octet_stream().to_string();
+                return builder.header(("Content-Encoding", coding)).content_type(content_type).file(&sibling).await.unwrap_or_else(io_error);
+            }
+        }
+        builder.file(path).await.unwrap_or_else(io_error)
+    }
+
+    async fn precompressed_sibling(&self, req: &Request, root: &Path, path: &Path) -> Option<(&'static str, PathBuf)> {
+        let accept_encoding = req.headers.get("Accept-Encoding")?;
+        for (coding, extension) in PRECOMPRESSED {
+            if !content_encoding::quality(accept_encoding, coding).is_some_and(|q| q > 0.0) {
+                continue;
+            }
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(".");
+            sibling.push(extension);
+            // a sibling linking out of the root is never served, the original file is sent instead
+            let Ok(Some(sibling)) = inside_root(root, Path::new(&sibling)).await else {
+                continue;
+            };
+            if tokio::fs::metadata(&sibling).await.is_ok_and(|metadata| metadata.is_file()) {
+                return Some((coding, sibling));
+            }
+        }
+        None
+    }
+}
+
+impl<'a> ConnectionHandler<'a> for ServeDir {
+    fn handle(&'a self, request: &'a Request, _payload: &'a BodyReader) -> std::pin::Pin<Box<dyn std::future::Future<Output = Response> + Send + 'a>> {
+        Box::pin(self.serve(request))
+    }
+}
+
+impl<'a> ServerHandler<'a> for ServeDir {}
+
+/// Path relative to the root, `None` if a segment could escape it or name something else than a file of the tree
+fn relative_path(request_path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in request_path.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => return None,
+            _ if segment.contains('\0') => return None,
+            // separators and prefixes of other platforms, e.g. `a\b` or `C:` on Windows
+            _ => match Path::new(segment).components().collect::<Vec<_>>()[..] {
+                [Component::Normal(name)] => relative.push(name),
+                _ => return None,
+            },
+        }
+    }
+    Some(relative)
+}
+
+/// `path` with its symbolic links resolved, `None` if it leads out of the canonical `root`;
+/// symbolic links are followed as long as they stay inside the root
+async fn inside_root(root: &Path, path: &Path) -> std::io::Result<Option<PathBuf>> {
+    let path = tokio::fs::canonicalize(path).await?;
+    Ok(path.starts_with(root).then_some(path))
+}
+
+/// HTML page linking every entry of `dir`, directories first
+async fn listing(dir: &Path, request_path: &str, has_parent: bool) -> std::io::Result<String> {
+    let mut entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let is_dir = entry.file_type().await.is_ok_and(|file_type| file_type.is_dir());
+        entries.push((!is_dir, entry.file_name().to_string_lossy().into_owned()));
+    }
+    entries.sort_unstable();
+
+    let title = html_escape(request_path);
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<ul>\n");
+    if has_parent {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let slash = if is_file { "" } else { "/" };
+        // `./` keeps names with a colon from being read as a scheme
+        let _ = writeln!(html, "<li><a href=\"./{}{slash}\">{}{slash}</a></li>", html_escape(&percent_encode_path(&name)), html_escape(&name));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes everything but unreserved characters and `/` (RFC 3986 2.3)
+fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'/') {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{b:02X}");
+        }
+    }
+    encoded
+}
+
+fn io_error(err: std::io::Error) -> Response {
+    match err.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => not_found(),
+        ErrorKind::PermissionDenied => forbidden(),
+        _ => Response::build().status(StatusCode::INTERNAL_SERVER_ERROR).body("Internal Server Error"),
+    }
+}
+
+fn not_found() -> Response {
+    Response::build().status(StatusCode::NOT_FOUND).body("Not Found")
+}
+
+fn forbidden() -> Response {
+    Response::build().status(StatusCode::FORBIDDEN).body("Forbidden")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{block_on, extensions::Extensions, headers::Headers, uri::Uri, version::Version};
+    use super::*;
+
+    fn get(target: &str) -> Request {
+        let uri = Uri::parse(target).unwrap();
+        Request { method: Method::GET, path: uri.path().to_owned(), uri, version: Version::Http11, headers: Headers::new(), extensions: Extensions::new(), body: None }
+    }
+
+    #[test]
+    fn redirects_directories_relatively() {
+        let root = std::env::temp_dir().join(format!("http-tokio-serve-dir-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("evil.example")).unwrap();
+        let serve_dir = ServeDir::new(&root);
+        for (target, location) in [("/evil.example", "./evil.example/"), ("//evil.example", "./evil.example/"), ("/evil.example?a=1", "./evil.example/?a=1")] {
+            let res = block_on(serve_dir.serve(&get(target)));
+            assert_eq!(res.status.code, 301, "{target}");
+            assert_eq!(res.headers.get("Location"), Some(location), "{target}");
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
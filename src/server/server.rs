@@ -1,22 +1,160 @@
-use std::future::Future;
+use std::{future::{poll_fn, Future}, pin::pin, task::Poll};
 
-use tokio::{net::{TcpListener, ToSocketAddrs}, task};
-use tracing::warn;
-use crate::{server::{Connection, ConnectionHandler}};
+use tokio::{net::{TcpListener, ToSocketAddrs}, sync::watch, task};
+use tracing::{info, warn};
+use crate::{server::{Connection, ConnectionHandler}, Compression, Limits};
+#[cfg(feature = "http2")]
+use crate::server::Http2;
+#[cfg(feature = "tls")]
+use crate::server::Tls;
 
 pub async fn run_server<A: ToSocketAddrs>(addr: A, handler: impl for<'a> ServerHandler<'a>) -> tokio::io::Result<()> {
-    let server = TcpListener::bind(addr).await?;
-    loop {
-        match server.accept().await {
-            Ok((stream, addr)) => {
-                let conn = Connection::new(stream, addr);
-                task::spawn(conn.handle_with(handler.clone()));
+    Server::bind(addr).await?.serve(handler).await
+}
+
+/// Listening server, applies its configuration to every accepted connection.
+/// 
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::{server::Server, BodyReader, Limits, Request, Response};
+/// # async fn handler(_req: &Request, _body: &BodyReader) -> Response {
+/// #     Response::build().body("hello")
+/// # }
+/// # async fn run() -> std::io::Result<()> {
+/// Server::bind("0.0.0.0:8080").await?
+///     .limits(Limits::default().max_headers(50))
+///     .serve(handler)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+    listener: TcpListener,
+    limits: Limits,
+    decompress_requests: bool,
+    compression: Option<Compression>,
+    #[cfg(feature = "http2")]
+    http2: Option<Http2>,
+    #[cfg(feature = "tls")]
+    tls: Option<Tls>,
+}
+
+impl Server {
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> tokio::io::Result<Self> {
+        Ok(Self { listener: TcpListener::bind(addr).await?, limits: Limits::default(), decompress_requests: false, compression: None, #[cfg(feature = "http2")] http2: None, #[cfg(feature = "tls")] tls: None })
+    }
+
+    /// Sets the size limits enforced on incoming requests.
+    /// 
+    /// Default is `Limits::default()`.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Decompresses `gzip`, `deflate` and `br` request bodies as they are read, see `Connection::decompress_requests`.
+    /// 
+    /// Default is `false`.
+    pub fn decompress_requests(mut self, enabled: bool) -> Self {
+        self.decompress_requests = enabled;
+        self
+    }
+
+    /// Compresses responses according to the request's `Accept-Encoding`, see `Compression`.
+    /// 
+    /// Default is no compression.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Serves connections starting with the HTTP/2 preface as HTTP/2, see `Http2`.
+    /// 
+    /// Default is HTTP/1.x only.
+    #[cfg(feature = "http2")]
+    pub fn http2(mut self, http2: Http2) -> Self {
+        self.http2 = Some(http2);
+        self
+    }
+
+    /// Serves HTTPS, the TLS handshake of each connection runs in its own task, see `Tls`.
+    /// 
+    /// Default is plain TCP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub async fn serve(self, handler: impl for<'a> ServerHandler<'a>) -> tokio::io::Result<()> {
+        self.serve_with_graceful_shutdown(handler, std::future::pending()).await
+    }
+
+    /// Serves until `signal` resolves, then stops accepting connections and waits for the open ones to close:
+    /// idle HTTP/1 connections are closed right away, busy ones after their current response,
+    /// HTTP/2 connections are sent GOAWAY and complete the streams in progress.
+    ///
+    /// Upgraded connections, e.g. WebSockets, are waited for until their handler returns;
+    /// wrap the returned future in a timeout to bound the wait.
+    ///
+    /// Code example:
+    /// ```rust,no_run
+    /// # use http_tokio::{server::Server, BodyReader, Request, Response};
+    /// # async fn handler(_req: &Request, _body: &BodyReader) -> Response {
+    /// #     Response::build().body("hello")
+    /// # }
+    /// # async fn run() -> std::io::Result<()> {
+    /// let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    /// // `stop` is sent from a signal handler or an admin endpoint
+    /// # drop(stop);
+    /// Server::bind("0.0.0.0:8080").await?
+    ///     .serve_with_graceful_shutdown(handler, async {
+    ///         let _ = stopped.await;
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve_with_graceful_shutdown(self, handler: impl for<'a> ServerHandler<'a>, signal: impl Future<Output = ()>) -> tokio::io::Result<()> {
+        // every connection holds a receiver, the channel closes once they are all done
+        let (shutdown, _) = watch::channel(false);
+        let mut signal = pin!(signal);
+        loop {
+            let accepted = poll_fn(|cx| match signal.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(None),
+                Poll::Pending => self.listener.poll_accept(cx).map(Some),
+            })
+            .await;
+            match accepted {
+                Some(Ok((stream, addr))) => {
+                    let mut conn = Connection::new(stream, addr).limits(self.limits).decompress_requests(self.decompress_requests).shutdown(shutdown.subscribe());
+                    if let Some(compression) = self.compression {
+                        conn = conn.compression(compression);
+                    }
+                    #[cfg(feature = "http2")]
+                    if let Some(http2) = self.http2 {
+                        conn = conn.http2(http2);
+                    }
+                    #[cfg(feature = "tls")]
+                    if let Some(tls) = &self.tls {
+                        conn = conn.tls(tls.clone());
+                    }
+                    task::spawn(conn.handle_with(handler.clone()));
+                }
+                Some(Err(err)) => {
+                    warn!( error = %err, kind = ?err.kind(), "Failed to accept incoming connection");
+                    handler.clone().handle_connection_error(err).await;
+                },
+                None => break,
             }
-            Err(err) => {
-                warn!( error = %err, kind = ?err.kind(), "Failed to accept incoming connection");
-                handler.clone().handle_connection_error(err).await;
-            },
         }
+
+        // new connections are refused from now on
+        drop(self.listener);
+        info!(connections = shutdown.receiver_count(), "Shutting down, waiting for open connections to close");
+        shutdown.send_replace(true);
+        shutdown.closed().await;
+        Ok(())
     }
 }
 
@@ -0,0 +1,194 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{Arc, PoisonError, RwLock}};
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        pki_types::{pem::{self, PemObject}, CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use crate::TcpIO;
+
+/// TLS settings for serving HTTPS, certificate chains and private keys are loaded from PEM files.
+///
+/// The certificate is picked by the server name the client asks for (SNI), exact names first,
+/// then wildcard names like `*.example.com`, then the default certificate.
+/// ALPN offers `h2` when HTTP/2 is enabled and `http/1.1`.
+///
+/// Clones share their certificates: `reload` swaps them for every connection accepted afterwards,
+/// without restarting the listener.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use http_tokio::{server::{Server, Tls}, BodyReader, Request, Response};
+/// # async fn handler(_req: &Request, _body: &BodyReader) -> Response {
+/// #     Response::build().body("hello")
+/// # }
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let tls = Tls::new("certs/default.pem", "certs/default.key")?
+///     .sni("api.example.com", "certs/api.pem", "certs/api.key")?;
+///
+/// let reloader = tls.clone();
+/// tokio::spawn(async move {
+///     // renewed certificates are picked up once a day
+///     let mut daily = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+///     loop {
+///         daily.tick().await;
+///         if let Err(err) = reloader.reload() {
+///             eprintln!("keeping the current certificates: {err}");
+///         }
+///     }
+/// });
+///
+/// Server::bind("0.0.0.0:443").await?
+///     .tls(tls)
+///     .serve(handler)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Tls {
+    certificates: Arc<Certificates>,
+    http1: Arc<ServerConfig>,
+    #[cfg(feature = "http2")]
+    http2: Arc<ServerConfig>,
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("could not load {}: {}", .0.display(), .1)]
+    Pem(PathBuf, pem::Error),
+    #[error("no certificate found in {}", .0.display())]
+    NoCertificate(PathBuf),
+    #[error("invalid certificate or private key: {0}")]
+    Rustls(#[from] tokio_rustls::rustls::Error),
+}
+
+impl Tls {
+    /// Loads the default certificate, sent to clients whose server name has no certificate of its own.
+    ///
+    /// `cert` holds the certificate chain, leaf first, `key` the matching PKCS#8, PKCS#1 or SEC1 private key.
+    pub fn new(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+        let files = CertFiles { cert: cert.as_ref().to_path_buf(), key: key.as_ref().to_path_buf() };
+        let default = files.load(&provider)?;
+        let certificates = Arc::new(Certificates {
+            provider: provider.clone(),
+            loaded: RwLock::new(Loaded { default: (files, default), by_name: HashMap::new() }),
+        });
+        let config = |alpn: &[&[u8]]| -> Result<Arc<ServerConfig>, TlsError> {
+            let mut config = ServerConfig::builder_with_provider(provider.clone())
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_cert_resolver(certificates.clone());
+            config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+            Ok(Arc::new(config))
+        };
+        Ok(Self {
+            http1: config(&[b"http/1.1"])?,
+            #[cfg(feature = "http2")]
+            http2: config(&[b"h2", b"http/1.1"])?,
+            certificates,
+        })
+    }
+
+    /// Adds the certificate sent to clients asking for `server_name`, e.g. `api.example.com` or `*.example.com`.
+    pub fn sni(self, server_name: impl Into<String>, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let files = CertFiles { cert: cert.as_ref().to_path_buf(), key: key.as_ref().to_path_buf() };
+        let key = files.load(&self.certificates.provider)?;
+        self.certificates.write().by_name.insert(server_name.into().to_ascii_lowercase(), (files, key));
+        Ok(self)
+    }
+
+    /// Reads every certificate and key file again, e.g. after the certificates were renewed.
+    ///
+    /// The certificates are swapped only if all of them load, otherwise the current ones are kept.
+    /// Handshakes in progress and established connections are not affected.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let provider = &self.certificates.provider;
+        let reloaded = {
+            let loaded = self.certificates.read();
+            let mut by_name = HashMap::with_capacity(loaded.by_name.len());
+            for (name, (files, _)) in &loaded.by_name {
+                by_name.insert(name.clone(), (files.clone(), files.load(provider)?));
+            }
+            let default = &loaded.default.0;
+            Loaded { default: (default.clone(), default.load(provider)?), by_name }
+        };
+        *self.certificates.write() = reloaded;
+        Ok(())
+    }
+
+    /// Performs the TLS handshake, offering `h2` through ALPN if `http2` is set
+    #[cfg_attr(not(feature = "http2"), allow(unused_variables))]
+    pub(super) async fn accept(&self, stream: TcpStream, http2: bool) -> std::io::Result<TcpIO> {
+        #[cfg(feature = "http2")]
+        let config = if http2 { &self.http2 } else { &self.http1 };
+        #[cfg(not(feature = "http2"))]
+        let config = &self.http1;
+        let stream = TlsAcceptor::from(config.clone()).accept(stream).await?;
+        Ok(TcpIO::tls(stream))
+    }
+}
+
+/// Certificates picked during each handshake, shared by the server configurations
+#[derive(Debug)]
+struct Certificates {
+    provider: Arc<CryptoProvider>,
+    loaded: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    default: (CertFiles, Arc<CertifiedKey>),
+    /// keyed by lowercase server name
+    by_name: HashMap<String, (CertFiles, Arc<CertifiedKey>)>,
+}
+
+impl Certificates {
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Loaded> {
+        self.loaded.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Loaded> {
+        self.loaded.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let loaded = self.read();
+        let by_name = client_hello.server_name().and_then(|name| {
+            let name = name.to_ascii_lowercase();
+            // a wildcard only stands for the leftmost label (RFC 6125 6.4.3)
+            let wildcard = name.split_once('.').map(|(_, parent)| format!("*.{parent}"));
+            loaded.by_name.get(&name).or_else(|| loaded.by_name.get(&wildcard?))
+        });
+        Some(by_name.unwrap_or(&loaded.default).1.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CertFiles {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl CertFiles {
+    fn load(&self, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, TlsError> {
+        let chain = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| TlsError::Pem(self.cert.clone(), err))?;
+        if chain.is_empty() {
+            return Err(TlsError::NoCertificate(self.cert.clone()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key).map_err(|err| TlsError::Pem(self.key.clone(), err))?;
+        Ok(Arc::new(CertifiedKey::from_der(chain, key, provider)?))
+    }
+}
@@ -0,0 +1,194 @@
+//! Server-Sent Events (`text/event-stream`), see `ResponseBuilder::sse`.
+
+use std::{fmt::Write, pin::Pin, task::{Context, Poll}, time::Duration};
+use bytes::Bytes;
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+use tokio_stream::Stream;
+use crate::response::ResponseError;
+
+/// Interval of the keep-alive comments sent by `ResponseBuilder::sse` while no event is sent
+pub(crate) const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Sent when no event was sent for the keep-alive interval, comments are ignored by clients
+const KEEP_ALIVE_COMMENT: &[u8] = b":\n\n";
+
+/// A single event of an event stream.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::Event;
+/// let event = Event::new()
+///     .id("42")
+///     .event("price")
+///     .data("{\"symbol\":\"ACME\",\"price\":12.5}");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the client's last event ID, sent back in the `Last-Event-ID` header when it reconnects.
+    ///
+    /// Line breaks are removed.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Event type dispatched by the client, `message` when not set.
+    ///
+    /// Line breaks are removed.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Event payload, multi-line data is sent as one `data` field per line and joined back by the client.
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Time the client waits before reconnecting after the connection is lost.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Comment ignored by the client, multi-line comments are sent as one comment per line.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Wire format of the event, terminated by a blank line
+    pub(crate) fn to_bytes(&self) -> Bytes {
+        let mut buf = String::new();
+        if let Some(comment) = &self.comment {
+            lines(comment).for_each(|line| { let _ = writeln!(buf, ": {line}"); });
+        }
+        if let Some(event) = &self.event {
+            let _ = writeln!(buf, "event: {event}");
+        }
+        if let Some(data) = &self.data {
+            lines(data).for_each(|line| { let _ = writeln!(buf, "data: {line}"); });
+        }
+        if let Some(id) = &self.id {
+            let _ = writeln!(buf, "id: {id}");
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(buf, "retry: {}", retry.as_millis());
+        }
+        buf.push('\n');
+        Bytes::from(buf)
+    }
+}
+
+/// Lines of `text` split on `\r\n`, `\r` and `\n`, the line terminators of the event stream format
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n').flat_map(|line| line.strip_suffix('\r').unwrap_or(line).split('\r'))
+}
+
+fn single_line(mut value: String) -> String {
+    value.retain(|c| c != '\r' && c != '\n');
+    value
+}
+
+/// Body of an event stream response: the formatted events, with a keep-alive comment whenever
+/// no event was sent for the keep-alive interval, so that proxies don't close an idle connection.
+///
+/// `ResponseBuilder::sse` builds the response from it, `ResponseBuilder::stream` takes it as is.
+pub struct EventStream<S> {
+    events: S,
+    keep_alive: Option<Interval>,
+}
+
+impl<S> EventStream<S> {
+    /// Stream of `events` with a keep-alive comment every `keep_alive`, `None` or zero for no keep-alive
+    pub fn new(events: S, keep_alive: Option<Duration>) -> Self {
+        let keep_alive = keep_alive.filter(|period| !period.is_zero()).map(|period| {
+            let mut interval = interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        Self { events, keep_alive }
+    }
+}
+
+impl<S: Stream<Item = Event> + Unpin> Stream for EventStream<S> {
+    type Item = Result<Bytes, ResponseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.events).poll_next(cx) {
+            Poll::Ready(Some(event)) => {
+                if let Some(keep_alive) = &mut this.keep_alive {
+                    keep_alive.reset();
+                }
+                return Poll::Ready(Some(Ok(event.to_bytes())));
+            },
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {},
+        }
+        match this.keep_alive.as_mut().map(|keep_alive| keep_alive.poll_tick(cx)) {
+            Some(Poll::Ready(_)) => Poll::Ready(Some(Ok(Bytes::from_static(KEEP_ALIVE_COMMENT)))),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::{iter, StreamExt};
+    use crate::block_on;
+    use super::*;
+
+    fn text(event: Event) -> String {
+        String::from_utf8(event.to_bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn serializes_fields() {
+        assert_eq!(text(Event::new()), "\n");
+        assert_eq!(text(Event::new().data("hi")), "data: hi\n\n");
+        let event = Event::new().comment("note").event("price").data("{}").id("42").retry(Duration::from_secs(3));
+        assert_eq!(text(event), ": note\nevent: price\ndata: {}\nid: 42\nretry: 3000\n\n");
+    }
+
+    #[test]
+    fn splits_multi_line_data_on_every_line_terminator() {
+        let event = Event::new().data("a\nb\r\nc\rd\n").comment("x\r\ny");
+        assert_eq!(text(event), ": x\n: y\ndata: a\ndata: b\ndata: c\ndata: d\ndata: \n\n");
+        assert_eq!(text(Event::new().data("\r\r\n")), "data: \ndata: \ndata: \n\n");
+    }
+
+    #[test]
+    fn removes_line_breaks_from_single_line_fields() {
+        let event = Event::new().event("a\r\nb").id("4\n2\r");
+        assert_eq!(text(event), "event: ab\nid: 42\n\n");
+    }
+
+    #[test]
+    fn sends_keep_alive_comments_while_idle() {
+        block_on(async {
+            let events = iter([Event::new().data("1")]).chain(tokio_stream::pending());
+            let mut stream = EventStream::new(events, Some(Duration::from_millis(10)));
+            assert_eq!(stream.next().await.unwrap().unwrap(), "data: 1\n\n");
+            assert_eq!(stream.next().await.unwrap().unwrap(), KEEP_ALIVE_COMMENT);
+            assert_eq!(stream.next().await.unwrap().unwrap(), KEEP_ALIVE_COMMENT);
+
+            let mut stream = EventStream::new(iter([Event::new().data("1")]), None);
+            assert_eq!(stream.next().await.unwrap().unwrap(), "data: 1\n\n");
+            assert!(stream.next().await.is_none());
+        });
+    }
+}
@@ -1,25 +1,62 @@
-use std::pin::Pin;
+use std::{io, pin::Pin, task::{Context, Poll}};
+use bytes::{Buf, Bytes};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader, BufWriter},
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadBuf},
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream, ToSocketAddrs,
     },
 };
+#[cfg(feature = "tls")]
+use tokio_rustls::server::TlsStream;
 
 // pinned heap pointer for Send enabled cheap ownership passing (maybe?)
 pub struct TcpIO(Pin<Box<InnerTcpIO>>);
 
 struct InnerTcpIO {
-    reader: BufReader<OwnedReadHalf>,
-    writer: BufWriter<OwnedWriteHalf>,
+    reader: BufReader<ReadHalf>,
+    writer: BufWriter<WriteHalf>,
+}
+
+/// Read half of a connection, plain TCP or TLS
+pub struct ReadHalf {
+    stream: ReadStream,
+    /// input put back with `TcpIO::unread`, read before the stream
+    rewound: Bytes,
+}
+
+enum ReadStream {
+    Tcp(OwnedReadHalf),
+    #[cfg(feature = "tls")]
+    Tls(tokio::io::ReadHalf<TlsStream<TcpStream>>),
+}
+
+/// Write half of a connection, plain TCP or TLS
+pub struct WriteHalf(WriteStream);
+
+enum WriteStream {
+    Tcp(OwnedWriteHalf),
+    #[cfg(feature = "tls")]
+    Tls(tokio::io::WriteHalf<TlsStream<TcpStream>>),
 }
 
 impl TcpIO {
     pub fn new(stream: TcpStream) -> Self {
         let (read_half, write_half) = stream.into_split();
-        let reader = BufReader::new(read_half);
-        let writer = BufWriter::new(write_half);
+        Self::from_halves(ReadStream::Tcp(read_half), WriteStream::Tcp(write_half))
+    }
+
+    /// Connection whose TLS handshake is complete, reads and writes go through the TLS session
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(stream: TlsStream<TcpStream>) -> Self {
+        let (read_half, write_half) = tokio::io::split(stream);
+        Self::from_halves(ReadStream::Tls(read_half), WriteStream::Tls(write_half))
+    }
+
+    fn from_halves(read_half: ReadStream, write_half: WriteStream) -> Self {
+        let reader = BufReader::new(ReadHalf { stream: read_half, rewound: Bytes::new() });
+        let writer = BufWriter::new(WriteHalf(write_half));
         Self(Box::pin(InnerTcpIO { reader, writer }))
     }
 
@@ -31,18 +68,168 @@ impl TcpIO {
         Ok(Self::new(stream))
     }
 
-    pub fn reader(&mut self) -> &mut BufReader<OwnedReadHalf> {
+    pub fn reader(&mut self) -> &mut BufReader<ReadHalf> {
         &mut self.0.reader
     }
 
-    pub fn writer(&mut self) -> &mut BufWriter<OwnedWriteHalf> {
+    pub fn writer(&mut self) -> &mut BufWriter<WriteHalf> {
         &mut self.0.writer
     }
 
+    /// Reader and writer borrowed at the same time, e.g. to copy one into the other on an upgraded connection
+    pub fn split(&mut self) -> (&mut BufReader<ReadHalf>, &mut BufWriter<WriteHalf>) {
+        let inner = &mut *self.0;
+        (&mut inner.reader, &mut inner.writer)
+    }
+
+    /// Whether the connection is encrypted with TLS
+    pub fn is_tls(&self) -> bool {
+        self.0.writer.get_ref().tcp_stream().is_none()
+    }
+
+    /// Puts `bytes`, already consumed from the reader, back in front of the input still to be read
+    #[cfg(feature = "http2")]
+    pub(crate) fn unread(&mut self, bytes: &[u8]) {
+        let reader = &mut self.0.reader;
+        let mut rewound = Vec::with_capacity(bytes.len() + reader.buffer().len() + reader.get_ref().rewound.len());
+        rewound.extend_from_slice(bytes);
+        rewound.extend_from_slice(reader.buffer());
+        rewound.extend_from_slice(&reader.get_ref().rewound);
+        let buffered = reader.buffer().len();
+        reader.consume(buffered);
+        reader.get_mut().rewound = rewound.into();
+    }
+
     pub async fn read_line(&mut self) -> Result<(usize, String), tokio::io::Error> {
         let mut buf = String::new();
         let len = self.0.reader.read_line(&mut buf).await?;
         let parsed = buf.trim_end().to_string(); // remove line terminators \r\n
         Ok((len, parsed))
     }
+
+    /// Sends `len` bytes of `file` from `offset` straight from the page cache to the socket, without copying them
+    /// through user space; buffered output is flushed first.
+    ///
+    /// Returns the number of bytes sent, short if the file ended early,
+    /// or `None` when zero-copy isn't supported, e.g. on TLS connections, so that the caller falls back to copying.
+    #[cfg(target_os = "linux")]
+    pub(crate) async fn send_file(&mut self, file: &File, mut offset: u64, len: u64) -> tokio::io::Result<Option<u64>> {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        /// Largest count transferred by a single sendfile(2) call
+        const MAX_SENDFILE: u64 = 0x7fff_f000;
+
+        self.0.writer.flush().await?;
+        let Some(stream) = self.0.writer.get_ref().tcp_stream() else {
+            // TLS records are encrypted in user space, the kernel can't send the file as is
+            return Ok(None);
+        };
+        let (out_fd, in_fd) = (stream.as_raw_fd(), file.as_raw_fd());
+        let mut sent = 0;
+        while sent < len {
+            stream.writable().await?;
+            let count = (len - sent).min(MAX_SENDFILE) as usize;
+            let result = stream.try_io(Interest::WRITABLE, || {
+                let mut off = offset as libc::off_t;
+                // SAFETY: both descriptors stay open for the duration of the call, `off` is a valid pointer
+                match unsafe { libc::sendfile(out_fd, in_fd, &mut off, count) } {
+                    n if n < 0 => Err(std::io::Error::last_os_error()),
+                    n => Ok(n as u64),
+                }
+            });
+            match result {
+                Ok(0) => break,
+                Ok(n) => {
+                    sent += n;
+                    offset += n;
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                // files that can't be mmap'ed, e.g. on some FUSE or proc filesystems
+                Err(err) if sent == 0 && matches!(err.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP)) => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Some(sent))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) async fn send_file(&mut self, _file: &File, _offset: u64, _len: u64) -> tokio::io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// Reads go through the read buffer, bytes already buffered are read first; writes are buffered until flushed
+impl AsyncRead for TcpIO {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(self.reader()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpIO {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(self.writer()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(self.writer()).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(self.writer()).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for ReadHalf {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.rewound.is_empty() {
+            let len = this.rewound.len().min(buf.remaining());
+            buf.put_slice(&this.rewound[..len]);
+            this.rewound.advance(len);
+            return Poll::Ready(Ok(()));
+        }
+        match &mut this.stream {
+            ReadStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            ReadStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl WriteHalf {
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        match &self.0 {
+            WriteStream::Tcp(stream) => Some(stream.as_ref()),
+            #[cfg(feature = "tls")]
+            WriteStream::Tls(_) => None,
+        }
+    }
+}
+
+impl AsyncWrite for WriteHalf {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().0 {
+            WriteStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            WriteStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            WriteStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            WriteStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    /// Sends the TLS `close_notify` alert before shutting down the write side
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            WriteStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            WriteStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
 }
@@ -0,0 +1,51 @@
+use std::{future::Future, pin::Pin, task::{Context, Poll}};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use crate::TcpIO;
+
+/// Resolves to the connection's IO once it has been switched to another protocol, see `Request::on_upgrade`.
+///
+/// The IO keeps its read buffer: bytes the client sent right after the request head are read first.
+///
+/// Code example:
+/// ```rust,no_run
+/// # use http_tokio::{BodyReader, Request, Response, StatusCode};
+/// async fn handler(req: &Request, _body: &BodyReader) -> Response {
+///     let Some(on_upgrade) = req.on_upgrade().await else {
+///         return Response::build().status(StatusCode::BAD_REQUEST).body("upgrade expected");
+///     };
+///     tokio::spawn(async move {
+///         if let Ok(mut io) = on_upgrade.await {
+///             let (reader, writer) = io.split();
+///             let _ = tokio::io::copy_buf(reader, writer).await;
+///         }
+///     });
+///     Response::build()
+///         .status(StatusCode::SWITCHING_PROTOCOLS)
+///         .header(("Connection", "upgrade"))
+///         .header(("Upgrade", "echo"))
+///         .end()
+/// }
+/// ```
+pub struct OnUpgrade(oneshot::Receiver<TcpIO>);
+
+impl OnUpgrade {
+    pub(crate) fn pair() -> (oneshot::Sender<TcpIO>, Self) {
+        let (tx, rx) = oneshot::channel();
+        (tx, Self(rx))
+    }
+}
+
+impl Future for OnUpgrade {
+    type Output = Result<TcpIO, UpgradeError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map_err(|_| UpgradeError)
+    }
+}
+
+/// The connection wasn't switched: the response wasn't `101 Switching Protocols` (or `2xx` to `CONNECT`),
+/// it couldn't be sent, or the request body couldn't be drained before it.
+#[derive(Error, Debug)]
+#[error("connection was not upgraded")]
+pub struct UpgradeError;
@@ -0,0 +1,298 @@
+use std::fmt::Display;
+
+/// The four request-target forms defined by RFC 9112 section 3.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UriForm {
+    /// `/path?query`, used by every method when talking to an origin server
+    Origin,
+    /// `http://host:port/path?query`, used when talking to a proxy
+    Absolute,
+    /// `host:port`, only used by `CONNECT`
+    Authority,
+    /// `*`, only used by a server-wide `OPTIONS`
+    Asterisk,
+}
+
+/// Parsed request target.
+///
+/// Keeps the raw target as received together with its components;
+/// the path is percent-decoded, the query string is left untouched.
+/// Paths whose decoding would change their segments or isn't text, with `%2F`, `%00` or invalid UTF-8, are rejected.
+#[derive(Debug, Clone)]
+pub struct Uri {
+    raw: String,
+    form: UriForm,
+    scheme: Option<String>,
+    authority: Option<String>,
+    path: String,
+    query: Option<String>,
+}
+
+impl Uri {
+    pub fn parse(target: &str) -> Result<Self, UriError> {
+        if target.is_empty() {
+            return Err(UriError::Empty);
+        }
+        if let Some(c) = target.chars().find(|c| !c.is_ascii_graphic() || *c == '#') {
+            return Err(UriError::InvalidChar(c));
+        }
+
+        if target == "*" {
+            return Ok(Uri { raw: target.to_string(), form: UriForm::Asterisk, scheme: None, authority: None, path: String::new(), query: None });
+        }
+
+        if target.starts_with('/') {
+            let (path, query) = split_query(target);
+            return Ok(Uri {
+                raw: target.to_string(),
+                form: UriForm::Origin,
+                scheme: None,
+                authority: None,
+                path: decode_path(path)?,
+                query: query.map(str::to_string),
+            });
+        }
+
+        match target.split_once("://") {
+            Some((scheme, rest)) if is_scheme(scheme) => {
+                let (authority, path_and_query) = match rest.find(['/', '?']) {
+                    Some(i) => rest.split_at(i),
+                    None => (rest, ""),
+                };
+                validate_authority(authority, false)?;
+                let (path, query) = split_query(path_and_query);
+                Ok(Uri {
+                    raw: target.to_string(),
+                    form: UriForm::Absolute,
+                    scheme: Some(scheme.to_ascii_lowercase()),
+                    authority: Some(authority.to_string()),
+                    path: if path.is_empty() { "/".to_string() } else { decode_path(path)? },
+                    query: query.map(str::to_string),
+                })
+            }
+            _ => {
+                validate_authority(target, true)?;
+                Ok(Uri {
+                    raw: target.to_string(),
+                    form: UriForm::Authority,
+                    scheme: None,
+                    authority: Some(target.to_string()),
+                    path: String::new(),
+                    query: None,
+                })
+            }
+        }
+    }
+
+    /// The request target exactly as it appeared in the request line
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn form(&self) -> UriForm {
+        self.form
+    }
+
+    /// Lowercased scheme, only present in absolute-form
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    /// `[userinfo@]host[:port]`, present in absolute-form and authority-form
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    /// Percent-decoded path, empty for authority-form and asterisk-form
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Raw query string without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+}
+
+impl Display for Uri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum UriError {
+    #[error("empty request target")]
+    Empty,
+
+    #[error("invalid character in request target: {0:?}")]
+    InvalidChar(char),
+
+    #[error("invalid percent-encoding in request target")]
+    InvalidPercentEncoding,
+
+    #[error("encoded '/', NUL byte or invalid UTF-8 in request path")]
+    InvalidPath,
+
+    #[error("invalid authority in request target: {0:?}")]
+    InvalidAuthority(String),
+}
+
+fn split_query(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    }
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Checks `[userinfo@]host[:port]`; authority-form requires the port and forbids userinfo.
+fn validate_authority(authority: &str, require_port: bool) -> Result<(), UriError> {
+    let invalid = || UriError::InvalidAuthority(authority.to_string());
+    let host_port = match authority.rsplit_once('@') {
+        Some(_) if require_port => return Err(invalid()),
+        Some((_, host_port)) => host_port,
+        None => authority,
+    };
+
+    let (host, port) = if host_port.starts_with('[') {
+        // IP-literal
+        let end = host_port.find(']').ok_or_else(invalid)?;
+        let (host, rest) = host_port.split_at(end + 1);
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+        }
+    } else {
+        match host_port.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        }
+    };
+
+    if host.is_empty() || (!host.starts_with('[') && host.contains(['/', '?', '[', ']'])) {
+        return Err(invalid());
+    }
+    match port {
+        Some(port) if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) => Err(invalid()),
+        None if require_port => Err(invalid()),
+        _ => Ok(()),
+    }
+}
+
+/// Strict percent-decoding of a path: every `%` must be followed by two hex digits,
+/// and the decoded path must be valid UTF-8 without an encoded `/` splitting a segment or a NUL byte.
+fn decode_path(input: &str) -> Result<String, UriError> {
+    if !input.contains('%') {
+        return Ok(input.to_string());
+    }
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hi = bytes.get(i + 1).and_then(|b| hex_value(*b));
+            let lo = bytes.get(i + 2).and_then(|b| hex_value(*b));
+            match (hi, lo) {
+                (Some(hi), Some(lo)) if matches!(hi << 4 | lo, b'/' | 0) => return Err(UriError::InvalidPath),
+                (Some(hi), Some(lo)) => decoded.push(hi << 4 | lo),
+                _ => return Err(UriError::InvalidPercentEncoding),
+            }
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| UriError::InvalidPath)
+}
+
+pub(crate) fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_origin_form() {
+        let uri = Uri::parse("/users/42?sort=asc").unwrap();
+        assert_eq!(uri.form(), UriForm::Origin);
+        assert_eq!((uri.scheme(), uri.authority()), (None, None));
+        assert_eq!(uri.path(), "/users/42");
+        assert_eq!(uri.query(), Some("sort=asc"));
+        assert_eq!(Uri::parse("/").unwrap().query(), None);
+    }
+
+    #[test]
+    fn parses_absolute_form() {
+        let uri = Uri::parse("HTTP://user@example.com:8080/a?b").unwrap();
+        assert_eq!(uri.form(), UriForm::Absolute);
+        assert_eq!(uri.scheme(), Some("http"));
+        assert_eq!(uri.authority(), Some("user@example.com:8080"));
+        assert_eq!(uri.path(), "/a");
+        assert_eq!(uri.query(), Some("b"));
+
+        let uri = Uri::parse("http://[::1]?q").unwrap();
+        assert_eq!(uri.authority(), Some("[::1]"));
+        assert_eq!(uri.path(), "/");
+        assert_eq!(uri.query(), Some("q"));
+    }
+
+    #[test]
+    fn parses_authority_form() {
+        let uri = Uri::parse("example.com:443").unwrap();
+        assert_eq!(uri.form(), UriForm::Authority);
+        assert_eq!(uri.authority(), Some("example.com:443"));
+        assert_eq!((uri.path(), uri.query()), ("", None));
+        assert_eq!(Uri::parse("[::1]:443").unwrap().authority(), Some("[::1]:443"));
+        for target in ["example.com", "example.com:", "example.com:https", "user@example.com:443", ":443", "[::1]443"] {
+            assert!(matches!(Uri::parse(target), Err(UriError::InvalidAuthority(_))), "{target}");
+        }
+    }
+
+    #[test]
+    fn parses_asterisk_form() {
+        let uri = Uri::parse("*").unwrap();
+        assert_eq!(uri.form(), UriForm::Asterisk);
+        assert_eq!((uri.path(), uri.query(), uri.authority()), ("", None, None));
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        assert!(matches!(Uri::parse(""), Err(UriError::Empty)));
+        assert!(matches!(Uri::parse("/a b"), Err(UriError::InvalidChar(' '))));
+        assert!(matches!(Uri::parse("/a#b"), Err(UriError::InvalidChar('#'))));
+        assert!(matches!(Uri::parse("/é"), Err(UriError::InvalidChar('é'))));
+        assert!(matches!(Uri::parse("http:///a"), Err(UriError::InvalidAuthority(_))));
+    }
+
+    #[test]
+    fn decodes_path() {
+        let uri = Uri::parse("/a%20b/%C3%A9?q=%2F").unwrap();
+        assert_eq!(uri.path(), "/a b/é");
+        assert_eq!(uri.query(), Some("q=%2F"));
+        assert_eq!(uri.raw(), "/a%20b/%C3%A9?q=%2F");
+    }
+
+    #[test]
+    fn rejects_paths_changed_by_decoding() {
+        for target in ["/a%2Fb", "/a%2fb", "/a%00", "/%FF", "/%C3", "http://a/b%2F"] {
+            assert!(matches!(Uri::parse(target), Err(UriError::InvalidPath)), "{target}");
+        }
+        for target in ["/%", "/%2", "/%G0"] {
+            assert!(matches!(Uri::parse(target), Err(UriError::InvalidPercentEncoding)), "{target}");
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::fmt::Display;
+
+/// HTTP version of a request, echoed back in the response status line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Version {
+    Http10,
+    #[default]
+    Http11,
+    /// Only used by requests received over an HTTP/2 connection
+    Http2,
+}
+
+impl Version {
+    /// Parses `HTTP/1.x`; minor versions above 1 are treated as HTTP/1.1 (RFC 9110 6.2)
+    pub fn parse(version: &[u8]) -> Option<Self> {
+        match version {
+            b"HTTP/1.0" => Some(Version::Http10),
+            [b'H', b'T', b'T', b'P', b'/', b'1', b'.', minor] if minor.is_ascii_digit() => Some(Version::Http11),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+            Version::Http2 => "HTTP/2",
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
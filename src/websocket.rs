@@ -0,0 +1,688 @@
+//! WebSocket connections (RFC 6455) over an upgraded HTTP/1.1 connection.
+//!
+//! Code example:
+//! ```rust,no_run
+//! # use http_tokio::{websocket::{Message, WebSocketUpgrade}, BodyReader, Request, Response};
+//! async fn handler(req: &Request, _body: &BodyReader) -> Response {
+//!     match WebSocketUpgrade::from_request(req) {
+//!         Ok(upgrade) => upgrade.on_upgrade(|mut ws| async move {
+//!             while let Ok(Some(message)) = ws.recv().await {
+//!                 if let Message::Text(_) | Message::Binary(_) = message {
+//!                     if ws.send(message).await.is_err() {
+//!                         break;
+//!                     }
+//!                 }
+//!             }
+//!         }),
+//!         Err(err) => err.into_response(),
+//!     }
+//! }
+//! ```
+
+use std::{future::Future, time::Duration};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, time::timeout};
+use crate::{status_code::StatusCode, Method, Request, Response, TcpIO, Version};
+
+/// Appended to the client's key to compute `Sec-WebSocket-Accept` (RFC 6455 1.3)
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Time `WebSocket::close` waits for the client to answer the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Largest payload of a control frame (RFC 6455 5.5)
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// Validated WebSocket opening handshake, answered with `on_upgrade`.
+#[derive(Debug)]
+pub struct WebSocketUpgrade {
+    accept: String,
+    offered_protocols: Vec<String>,
+    protocol: Option<String>,
+    max_message_size: usize,
+    max_frame_size: usize,
+}
+
+impl WebSocketUpgrade {
+    /// Validates the opening handshake of `req` (RFC 6455 4.2.1).
+    pub fn from_request(req: &Request) -> Result<Self, HandshakeError> {
+        if req.method != Method::GET {
+            return Err(HandshakeError::MethodNotAllowed);
+        }
+        if req.version != Version::Http11 || !req.headers.contains_token("Upgrade", "websocket") || !req.headers.contains_token("Connection", "upgrade") {
+            return Err(HandshakeError::NotUpgrade);
+        }
+        match req.headers.get("Sec-WebSocket-Version").map(str::trim) {
+            Some("13") => {},
+            version => return Err(HandshakeError::UnsupportedVersion(version.unwrap_or_default().to_string())),
+        }
+        let key = req.headers.get("Sec-WebSocket-Key").map(str::trim).unwrap_or_default();
+        if !BASE64.decode(key).is_ok_and(|nonce| nonce.len() == 16) {
+            return Err(HandshakeError::InvalidKey);
+        }
+        let offered_protocols = req.headers.get_all("Sec-WebSocket-Protocol").map_or_else(Vec::new, |values| {
+            values
+                .iter()
+                .filter_map(|value| value.to_str())
+                .flat_map(|value| value.split(','))
+                .map(|protocol| protocol.trim().to_string())
+                .filter(|protocol| !protocol.is_empty())
+                .collect()
+        });
+        Ok(Self {
+            accept: accept_key(key),
+            offered_protocols,
+            protocol: None,
+            max_message_size: 16 * 1024 * 1024,
+            max_frame_size: 16 * 1024 * 1024,
+        })
+    }
+
+    /// Subprotocols offered by the client in `Sec-WebSocket-Protocol`, in its order of preference
+    pub fn offered_protocols(&self) -> &[String] {
+        &self.offered_protocols
+    }
+
+    /// Selects the first subprotocol offered by the client that is in `supported`, if any.
+    ///
+    /// Default is no subprotocol.
+    pub fn protocols<I, S>(mut self, supported: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let supported: Vec<S> = supported.into_iter().collect();
+        self.protocol = self.offered_protocols.iter().find(|offered| supported.iter().any(|protocol| protocol.as_ref() == offered.as_str())).cloned();
+        self
+    }
+
+    /// Sets the maximum size in bytes of a received message, fragments included.
+    ///
+    /// Default is 16 MiB, exceeding it closes the connection with status `1009`.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a single received frame.
+    ///
+    /// Default is 16 MiB, exceeding it closes the connection with status `1009`.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size.max(1);
+        self
+    }
+
+    /// `101 Switching Protocols` response accepting the handshake, `callback` receives the WebSocket once it has been sent
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut builder = Response::build().header(("Sec-WebSocket-Accept", &self.accept));
+        if let Some(protocol) = &self.protocol {
+            builder = builder.header(("Sec-WebSocket-Protocol", protocol));
+        }
+        let (max_message_size, max_frame_size) = (self.max_message_size, self.max_frame_size);
+        builder.upgrade("websocket", move |io| callback(WebSocket::new(io, max_message_size, max_frame_size)))
+    }
+}
+
+/// `Sec-WebSocket-Accept` value proving the handshake was understood
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+#[derive(Error, Debug)]
+pub enum HandshakeError {
+    #[error("WebSocket handshake must use GET")]
+    MethodNotAllowed,
+
+    #[error("request doesn't ask for a WebSocket upgrade over HTTP/1.1")]
+    NotUpgrade,
+
+    #[error("unsupported WebSocket version: {0:?}")]
+    UnsupportedVersion(String),
+
+    #[error("missing or invalid Sec-WebSocket-Key")]
+    InvalidKey,
+}
+
+impl HandshakeError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            HandshakeError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            HandshakeError::NotUpgrade | HandshakeError::UnsupportedVersion(_) => StatusCode::UPGRADE_REQUIRED,
+            HandshakeError::InvalidKey => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Error response telling the client how to retry: the supported method, protocol or version
+    pub fn into_response(self) -> Response {
+        let builder = Response::build().status(self.status_code());
+        let builder = match self {
+            HandshakeError::MethodNotAllowed => builder.header(("Allow", "GET")),
+            HandshakeError::NotUpgrade => builder.header(("Upgrade", "websocket")).header(("Connection", "upgrade")),
+            HandshakeError::UnsupportedVersion(_) => builder.header(("Sec-WebSocket-Version", "13")),
+            HandshakeError::InvalidKey => builder,
+        };
+        builder.body(self.to_string())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum WebSocketError {
+    #[error("WebSocket I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("WebSocket protocol violation: {0}")]
+    Protocol(&'static str),
+
+    #[error("text message is not valid UTF-8")]
+    InvalidUtf8,
+
+    #[error("message exceeds the limit of {0} bytes")]
+    MessageTooLarge(usize),
+
+    #[error("WebSocket connection closed")]
+    ConnectionClosed,
+}
+
+impl WebSocketError {
+    /// Status code of the close frame sent to the client (RFC 6455 7.4.1)
+    fn close_code(&self) -> Option<u16> {
+        match self {
+            WebSocketError::Protocol(_) => Some(1002),
+            WebSocketError::InvalidUtf8 => Some(1007),
+            WebSocketError::MessageTooLarge(_) => Some(1009),
+            WebSocketError::Io(_) | WebSocketError::ConnectionClosed => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Bytes),
+    /// Answered automatically with a pong carrying the same payload
+    Ping(Bytes),
+    Pong(Bytes),
+    /// Answered automatically, `recv` returns `None` afterwards
+    Close(Option<CloseFrame>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Text(text.to_string())
+    }
+}
+
+impl From<Bytes> for Message {
+    fn from(data: Bytes) -> Self {
+        Message::Binary(data)
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Self {
+        Message::Binary(data.into())
+    }
+}
+
+/// Status code and reason of a close frame, e.g. `1000` for a normal closure (RFC 6455 7.4)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Server side of a WebSocket connection.
+///
+/// Received messages are reassembled from their fragments, pings and close frames are answered automatically.
+/// `recv` is not cancel safe: a frame partially read when its future is dropped is lost along with the connection's framing.
+pub struct WebSocket {
+    io: TcpIO,
+    max_message_size: usize,
+    max_frame_size: usize,
+    /// Opcode and payload received so far of a fragmented message
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    fn new(io: TcpIO, max_message_size: usize, max_frame_size: usize) -> Self {
+        Self { io, max_message_size, max_frame_size, fragments: None, close_sent: false, close_received: false }
+    }
+
+    /// Next message from the client, `None` once the close handshake is done.
+    ///
+    /// Protocol violations close the connection with the matching status code before the error is returned.
+    pub async fn recv(&mut self) -> Result<Option<Message>, WebSocketError> {
+        if self.close_received {
+            return Ok(None);
+        }
+        match self.read_message().await {
+            Ok(message) => Ok(Some(message)),
+            Err(err) => {
+                if let Some(code) = err.close_code() {
+                    if !self.close_sent {
+                        self.close_sent = true;
+                        let _ = self.write_frame(OP_CLOSE, true, &close_payload(code, "")).await;
+                    }
+                }
+                Err(err)
+            },
+        }
+    }
+
+    /// Sends a message, data messages are sent in a single frame.
+    ///
+    /// Sending `Message::Close` starts the close handshake, nothing can be sent afterwards.
+    pub async fn send(&mut self, message: impl Into<Message>) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        match message.into() {
+            Message::Text(text) => self.write_frame(OP_TEXT, true, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OP_BINARY, true, &data).await,
+            Message::Ping(data) => self.write_control(OP_PING, &data).await,
+            Message::Pong(data) => self.write_control(OP_PONG, &data).await,
+            Message::Close(frame) => {
+                let payload = frame.map(|frame| close_payload(frame.code, &frame.reason)).unwrap_or_default();
+                self.close_sent = true;
+                self.write_control(OP_CLOSE, &payload).await
+            },
+        }
+    }
+
+    /// Closes the connection: sends a close frame with `code` and `reason`,
+    /// then waits for the client's close frame, discarding the messages received in between.
+    pub async fn close(mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if !self.close_sent {
+            self.send(Message::Close(Some(CloseFrame { code, reason: reason.to_string() }))).await?;
+        }
+        let _ = timeout(CLOSE_TIMEOUT, async {
+            while let Ok(Some(_)) = self.recv().await {}
+        })
+        .await;
+        self.io.writer().shutdown().await?;
+        Ok(())
+    }
+
+    async fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let buffered = self.fragments.as_ref().map_or(0, |(_, payload)| payload.len());
+            let frame = self.read_frame(buffered).await?;
+            match frame.opcode {
+                OP_CONTINUATION => {
+                    let Some((_, payload)) = &mut self.fragments else {
+                        return Err(WebSocketError::Protocol("continuation frame without a message to continue"));
+                    };
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        let (opcode, payload) = self.fragments.take().unwrap_or_default();
+                        return data_message(opcode, payload);
+                    }
+                },
+                OP_TEXT | OP_BINARY => {
+                    if self.fragments.is_some() {
+                        return Err(WebSocketError::Protocol("new message before the end of a fragmented message"));
+                    }
+                    if frame.fin {
+                        return data_message(frame.opcode, frame.payload);
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                },
+                OP_PING => {
+                    if !self.close_sent {
+                        self.write_frame(OP_PONG, true, &frame.payload).await?;
+                    }
+                    return Ok(Message::Ping(frame.payload.into()));
+                },
+                OP_PONG => return Ok(Message::Pong(frame.payload.into())),
+                _ => {
+                    let close = parse_close(&frame.payload)?;
+                    self.close_received = true;
+                    if !self.close_sent {
+                        // echoes the status code (RFC 6455 5.5.1)
+                        self.close_sent = true;
+                        let payload = close.as_ref().map(|close| close_payload(close.code, "")).unwrap_or_default();
+                        self.write_frame(OP_CLOSE, true, &payload).await?;
+                    }
+                    return Ok(Message::Close(close));
+                },
+            }
+        }
+    }
+
+    /// Reads and unmasks a frame, `buffered` bytes of the current message were already received
+    async fn read_frame(&mut self, buffered: usize) -> Result<Frame, WebSocketError> {
+        let reader = self.io.reader();
+        let mut head = [0u8; 2];
+        reader.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits set without a negotiated extension"));
+        }
+        if !matches!(opcode, OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG) {
+            return Err(WebSocketError::Protocol("unknown opcode"));
+        }
+        if head[1] & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("client frames must be masked"));
+        }
+        let len = match head[1] & 0x7F {
+            126 => reader.read_u16().await? as u64,
+            127 => reader.read_u64().await?,
+            len => len as u64,
+        };
+        let is_control = opcode & 0x8 != 0;
+        if is_control && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(WebSocketError::Protocol("control frames must be single frames of at most 125 bytes"));
+        }
+        if !is_control && len > self.max_frame_size as u64 {
+            return Err(WebSocketError::MessageTooLarge(self.max_frame_size));
+        }
+        if !is_control && buffered as u64 + len > self.max_message_size as u64 {
+            return Err(WebSocketError::MessageTooLarge(self.max_message_size));
+        }
+        let mut mask = [0u8; 4];
+        reader.read_exact(&mut mask).await?;
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload).await?;
+        payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+        Ok(Frame { fin, opcode, payload })
+    }
+
+    async fn write_control(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(WebSocketError::Protocol("control frames must be single frames of at most 125 bytes"));
+        }
+        self.write_frame(opcode, true, payload).await
+    }
+
+    /// Writes an unmasked frame, server frames are never masked (RFC 6455 5.1)
+    async fn write_frame(&mut self, opcode: u8, fin: bool, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mut head = Vec::with_capacity(10);
+        head.push(if fin { 0x80 } else { 0 } | opcode);
+        match payload.len() {
+            len @ 0..=125 => head.push(len as u8),
+            len @ 126..=0xFFFF => {
+                head.push(126);
+                head.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            len => {
+                head.push(127);
+                head.extend_from_slice(&(len as u64).to_be_bytes());
+            },
+        }
+        let writer = self.io.writer();
+        writer.write_all(&head).await?;
+        writer.write_all(payload).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+fn data_message(opcode: u8, payload: Vec<u8>) -> Result<Message, WebSocketError> {
+    if opcode == OP_TEXT {
+        String::from_utf8(payload).map(Message::Text).map_err(|_| WebSocketError::InvalidUtf8)
+    } else {
+        Ok(Message::Binary(payload.into()))
+    }
+}
+
+/// Status code followed by the UTF-8 reason, an empty payload has neither
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+    let [hi, lo, reason @ ..] = payload else {
+        return match payload.len() {
+            0 => Ok(None),
+            _ => Err(WebSocketError::Protocol("close frame with a truncated status code")),
+        };
+    };
+    let code = u16::from_be_bytes([*hi, *lo]);
+    // 1005, 1006 and 1015 are reserved for reporting and never sent (RFC 6455 7.4.1)
+    if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+        return Err(WebSocketError::Protocol("invalid close status code"));
+    }
+    let reason = std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+    Ok(Some(CloseFrame { code, reason: reason.to_string() }))
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    // the payload of a control frame is limited to 125 bytes, the reason is cut on a character boundary
+    let mut len = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+    while !reason.is_char_boundary(len) {
+        len -= 1;
+    }
+    payload.extend_from_slice(&reason.as_bytes()[..len]);
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::block_on;
+    use super::*;
+
+    /// Server end of a fresh connection and the client's end
+    async fn connection(max_message_size: usize, max_frame_size: usize) -> (WebSocket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (WebSocket::new(TcpIO::new(server), max_message_size, max_frame_size), client)
+    }
+
+    /// Client frame, masked as clients must
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            },
+        }
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    /// First byte and payload of the next server frame, which must not be masked
+    async fn server_frame(client: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0u8; 2];
+        client.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+        let len = match head[1] {
+            126 => client.read_u16().await.unwrap() as usize,
+            127 => client.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        client.read_exact(&mut payload).await.unwrap();
+        (head[0], payload)
+    }
+
+    /// Status code of the close frame the server sent
+    async fn close_code(client: &mut TcpStream) -> u16 {
+        let (head, payload) = server_frame(client).await;
+        assert_eq!(head, 0x80 | OP_CLOSE);
+        u16::from_be_bytes([payload[0], payload[1]])
+    }
+
+    #[test]
+    fn computes_accept_key() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn unmasks_frames_of_every_length_encoding() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            for len in [0, 125, 126, 0xFFFF, 0x10000] {
+                let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                client.write_all(&masked(true, OP_BINARY, &payload)).await.unwrap();
+                assert_eq!(ws.recv().await.unwrap(), Some(Message::Binary(payload.into())), "{len}");
+            }
+        });
+    }
+
+    #[test]
+    fn sends_unmasked_single_frames() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 16).await;
+            ws.send("hello").await.unwrap();
+            assert_eq!(server_frame(&mut client).await, (0x80 | OP_TEXT, b"hello".to_vec()));
+            let data = vec![7u8; 0x10000];
+            ws.send(data.clone()).await.unwrap();
+            assert_eq!(server_frame(&mut client).await, (0x80 | OP_BINARY, data));
+        });
+    }
+
+    #[test]
+    fn reassembles_fragments_around_control_frames() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(false, OP_TEXT, &"hé".as_bytes()[..2])).await.unwrap();
+            client.write_all(&masked(true, OP_PING, b"p")).await.unwrap();
+            client.write_all(&masked(false, OP_CONTINUATION, &"hé".as_bytes()[2..])).await.unwrap();
+            client.write_all(&masked(true, OP_CONTINUATION, b"llo")).await.unwrap();
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Ping(Bytes::from_static(b"p"))));
+            assert_eq!(server_frame(&mut client).await, (0x80 | OP_PONG, b"p".to_vec()));
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Text("héllo".to_owned())));
+        });
+    }
+
+    #[test]
+    fn rejects_interleaved_messages() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(false, OP_TEXT, b"a")).await.unwrap();
+            client.write_all(&masked(true, OP_TEXT, b"b")).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::Protocol(_))));
+            assert_eq!(close_code(&mut client).await, 1002);
+
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(true, OP_CONTINUATION, b"a")).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::Protocol(_))));
+            assert_eq!(close_code(&mut client).await, 1002);
+        });
+    }
+
+    #[test]
+    fn rejects_unmasked_and_reserved_frames() {
+        block_on(async {
+            for frame in [vec![0x81, 0x01, b'a'], vec![0xC1, 0x80, 0, 0, 0, 0], vec![0x83, 0x80, 0, 0, 0, 0]] {
+                let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+                client.write_all(&frame).await.unwrap();
+                assert!(matches!(ws.recv().await, Err(WebSocketError::Protocol(_))), "{frame:?}");
+                assert_eq!(close_code(&mut client).await, 1002);
+            }
+        });
+    }
+
+    #[test]
+    fn closes_with_1009_over_the_size_limits() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 4).await;
+            client.write_all(&masked(true, OP_BINARY, b"12345")).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::MessageTooLarge(4))));
+            assert_eq!(close_code(&mut client).await, 1009);
+
+            let (mut ws, mut client) = connection(6, 4).await;
+            client.write_all(&masked(false, OP_BINARY, b"1234")).await.unwrap();
+            client.write_all(&masked(true, OP_CONTINUATION, b"567")).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::MessageTooLarge(6))));
+            assert_eq!(close_code(&mut client).await, 1009);
+        });
+    }
+
+    #[test]
+    fn limits_control_frames() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(true, OP_PING, &[0; 126])).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::Protocol(_))));
+            assert_eq!(close_code(&mut client).await, 1002);
+
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(false, OP_PING, b"p")).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::Protocol(_))));
+            assert_eq!(close_code(&mut client).await, 1002);
+
+            // control frames aren't bound by the frame size limit, only by their own
+            let (mut ws, mut client) = connection(1 << 20, 1).await;
+            client.write_all(&masked(true, OP_PING, &[1; 125])).await.unwrap();
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Ping(vec![1; 125].into())));
+            assert!(matches!(ws.send(Message::Ping(vec![0; 126].into())).await, Err(WebSocketError::Protocol(_))));
+        });
+    }
+
+    #[test]
+    fn closes_with_1007_on_invalid_utf8() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(true, OP_TEXT, &[0xC3])).await.unwrap();
+            assert!(matches!(ws.recv().await, Err(WebSocketError::InvalidUtf8)));
+            assert_eq!(close_code(&mut client).await, 1007);
+        });
+    }
+
+    #[test]
+    fn echoes_the_close_status_code() {
+        block_on(async {
+            let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+            client.write_all(&masked(true, OP_CLOSE, &close_payload(1001, "bye"))).await.unwrap();
+            let close = Some(CloseFrame { code: 1001, reason: "bye".to_owned() });
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Close(close)));
+            assert_eq!(server_frame(&mut client).await, (0x80 | OP_CLOSE, 1001u16.to_be_bytes().to_vec()));
+            assert_eq!(ws.recv().await.unwrap(), None);
+            assert!(matches!(ws.send("late").await, Err(WebSocketError::ConnectionClosed)));
+
+            for payload in [&[0x03][..], &1005u16.to_be_bytes(), &999u16.to_be_bytes()] {
+                let (mut ws, mut client) = connection(1 << 20, 1 << 20).await;
+                client.write_all(&masked(true, OP_CLOSE, payload)).await.unwrap();
+                assert!(matches!(ws.recv().await, Err(WebSocketError::Protocol(_))), "{payload:?}");
+                assert_eq!(close_code(&mut client).await, 1002);
+            }
+        });
+    }
+
+    #[test]
+    fn cuts_close_reasons_on_a_character_boundary() {
+        let payload = close_payload(1000, &"é".repeat(100));
+        assert_eq!(payload.len(), 2 + 122);
+        assert!(std::str::from_utf8(&payload[2..]).is_ok());
+    }
+}